@@ -1,9 +1,65 @@
 //! Wiggle Puppy CLI - An autonomous AI agent loop runner.
 
 use clap::Parser;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use wiggle_puppy_core::{CompletionReason, Event, EventReceiver, Outcome, Prd, Runner, StopReason};
+use wiggle_puppy_core::{
+    completion::parse_detector, template::parse_var, CompletionDetector, CompletionPolicy,
+    CompletionReason, ErrorPattern, Event, EventEnvelope, EventReceiver, Hook, HookFailure,
+    Outcome, Prd, PromptDelivery, Runner, RunnerHandle, StopReason, Uuid, EVENT_SCHEMA_VERSION,
+};
+
+/// Exit code used when the run is cancelled by a signal (128 + SIGINT).
+const EXIT_CANCELLED: u8 = 130;
+
+/// Exit code used when the agent reports it is blocked (2 is taken by usage errors).
+const EXIT_AGENT_BLOCKED: u8 = 3;
+
+/// Exit code used when the agent is waiting for an answer from the user.
+const EXIT_AWAITING_INPUT: u8 = 4;
+
+/// Lines of failed verify output shown in the terminal.
+const VERIFY_DISPLAY_LINES: usize = 10;
+
+/// How run output is written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable progress output.
+    #[default]
+    Text,
+    /// A single JSON document with every event and the summary, written at the end.
+    Json,
+    /// One JSON object per line for each event as it happens, then the summary.
+    Jsonl,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
+            other => Err(format!(
+                "invalid output format '{}' (expected text, json or jsonl)",
+                other
+            )),
+        }
+    }
+}
 
 /// Wiggle Puppy - Run autonomous AI agent loops with completion detection.
 ///
@@ -15,23 +71,48 @@ use wiggle_puppy_core::{CompletionReason, Event, EventReceiver, Outcome, Prd, Ru
 #[command(name = "wiggle-puppy")]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    /// Path to the prompt file to use.
+    /// Prompt files to use, concatenated in order; `-` reads the prompt from stdin.
     ///
-    /// The prompt will be re-read each iteration, allowing for stateful prompts.
-    /// Conflicts with --prompt.
+    /// The files will be re-read each iteration, allowing for stateful prompts;
+    /// stdin is read once at startup and used in the position of the `-`. Files may include others with
+    /// `{{> path}}`. Conflicts with --prompt.
     #[arg(value_name = "PROMPT_FILE")]
-    pub prompt_file: Option<PathBuf>,
+    pub prompt_files: Vec<PathBuf>,
 
     /// Inline prompt text to use instead of a file.
     ///
-    /// Conflicts with the prompt file positional argument.
-    #[arg(short = 'p', long = "prompt", conflicts_with = "prompt_file")]
+    /// Conflicts with the prompt file positional arguments.
+    #[arg(short = 'p', long = "prompt", conflicts_with = "prompt_files")]
     pub prompt: Option<String>,
 
+    /// The prompt read from stdin, used where `-` appears among the prompt files.
+    #[arg(skip)]
+    pub stdin_prompt: Option<String>,
+
+    /// Prompt template variable as NAME=VALUE (can be specified multiple times).
+    ///
+    /// Referenced in the prompt as `{{NAME}}`. Built-in variables such as
+    /// `{{iteration}}` and `{{next_story.title}}` take precedence.
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        value_parser = parse_var,
+        action = clap::ArgAction::Append
+    )]
+    pub vars: Vec<(String, String)>,
+
+    /// Send the prompt as written, without expanding `{{> path}}` includes
+    /// or `{{NAME}}` variables.
+    ///
+    /// For prompts full of other template syntax; a single `{{` can instead
+    /// be escaped as `\{{`.
+    #[arg(long = "no-templating")]
+    pub no_templating: bool,
+
     /// Agent command to run.
     ///
     /// The agent will receive the prompt content via stdin or as an argument
-    /// depending on the agent-args configuration.
+    /// depending on --prompt-delivery.
     #[arg(
         short = 'a',
         long = "agent",
@@ -47,6 +128,15 @@ pub struct Cli {
     #[arg(long = "agent-args", default_value = "-p")]
     pub agent_args: String,
 
+    /// How the prompt is handed to the agent: arg, stdin, file, or placeholder.
+    ///
+    /// `arg` appends the prompt as the last argument, `stdin` pipes it in,
+    /// `file` passes the path of a temp file, and `placeholder` replaces
+    /// `{prompt}` / `{prompt_file}` inside --agent-args. Prompts too large for
+    /// the command line are sent via stdin automatically.
+    #[arg(long = "prompt-delivery", default_value = "arg")]
+    pub prompt_delivery: PromptDelivery,
+
     /// Maximum number of iterations before stopping.
     ///
     /// The loop will stop after this many iterations even if completion
@@ -61,9 +151,36 @@ pub struct Cli {
     #[arg(short = 's', long = "state")]
     pub state: Option<PathBuf>,
 
+    /// Path to a progress log file.
+    ///
+    /// The runner appends a timestamped entry after each iteration with the
+    /// story worked on, stories completed, duration, exit code, retries and
+    /// the last lines of agent output.
+    #[arg(long = "progress", value_name = "PATH")]
+    pub progress: Option<PathBuf>,
+
+    /// Path to a checkpoint file, rewritten after each iteration.
+    ///
+    /// Records the run id, iteration count, consecutive failures and a hash
+    /// of the run's settings so a crashed run can be resumed.
+    #[arg(long = "checkpoint", value_name = "PATH")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Continue the iteration count and failure counters from the checkpoint.
+    ///
+    /// --max-iterations stays the budget for the whole run. Starts fresh if
+    /// the checkpoint file does not exist yet.
+    #[arg(long = "resume", requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Resume even if the checkpoint was written with different settings.
+    #[arg(long = "force-resume", requires = "checkpoint")]
+    pub force_resume: bool,
+
     /// Completion phrase to detect in agent output.
     ///
-    /// When this phrase is detected in the agent's output, the loop completes.
+    /// When this phrase appears on a line by itself in the agent's output,
+    /// the loop completes.
     #[arg(
         short = 'c',
         long = "completion",
@@ -71,6 +188,34 @@ pub struct Cli {
     )]
     pub completion: String,
 
+    /// Add a per-run nonce to the completion phrase.
+    ///
+    /// The agent is told to output e.g. `<promise>COMPLETE-3f9a</promise>`,
+    /// so quoting the phrase from elsewhere doesn't complete the run.
+    #[arg(long = "completion-nonce")]
+    pub completion_nonce: bool,
+
+    /// Completion detector replacing the phrase check (can be specified multiple times).
+    ///
+    /// One of `substring:TEXT`, `regex:REGEX`, `line:TEXT` (on its own line),
+    /// `last-lines:N:TEXT`, `json:KEY=VALUE` or `consecutive:N:SPEC`. The run
+    /// completes when any detector matches.
+    #[arg(
+        long = "completion-detector",
+        value_name = "SPEC",
+        value_parser = parse_detector,
+        action = clap::ArgAction::Append
+    )]
+    pub completion_detectors: Vec<Box<dyn CompletionDetector>>,
+
+    /// Which signals complete the run.
+    ///
+    /// `any` accepts the completion phrase or every PRD story passing, `all`
+    /// requires both, `prd-only` ignores the phrase (and requires --state),
+    /// and `phrase-only` ignores the PRD.
+    #[arg(long = "completion-policy", default_value = "any")]
+    pub completion_policy: CompletionPolicy,
+
     /// Delay in seconds between iterations.
     ///
     /// A short delay between iterations can help prevent rate limiting
@@ -85,6 +230,15 @@ pub struct Cli {
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
 
+    /// Output format: text, json, or jsonl.
+    ///
+    /// `jsonl` writes each event as a JSON object on its own line as it
+    /// happens, followed by a summary object; `json` writes a single document
+    /// with all events and the summary when the run ends. Both carry a
+    /// `schema_version` and leave stderr for diagnostics.
+    #[arg(long = "output-format", default_value = "text")]
+    pub output_format: OutputFormat,
+
     /// Disable automatic completion instruction.
     ///
     /// By default, an instruction telling the agent to output the completion
@@ -93,9 +247,85 @@ pub struct Cli {
     pub no_auto_instruction: bool,
 
     /// Agent execution timeout in seconds.
+    ///
+    /// Covers the whole run, including time spent streaming output.
     #[arg(long = "agent-timeout", default_value = "900")]
     pub agent_timeout: u64,
 
+    /// Kill the agent after this many seconds without output (0 to disable).
+    #[arg(long = "idle-timeout", default_value = "0")]
+    pub idle_timeout: u64,
+
+    /// Seconds between SIGTERM and SIGKILL when cancelling the agent
+    #[arg(long = "kill-grace", default_value = "5")]
+    pub kill_grace: u64,
+
+    /// Seconds to sleep after a usage limit message that has no reset time.
+    ///
+    /// When the agent reports when its quota resets, the runner sleeps until
+    /// then instead; a reset time in the past uses this wait too. Usage
+    /// limits never count as failures.
+    #[arg(long = "usage-limit-wait", default_value = "3600")]
+    pub usage_limit_wait: u64,
+
+    /// Stop after this many usage limit waits in a row (0 for no limit).
+    #[arg(long = "max-usage-limit-waits", default_value = "6")]
+    pub max_usage_limit_waits: u32,
+
+    /// Shell command that must pass before completion is accepted.
+    ///
+    /// Runs with `sh -c` (e.g. "cargo test") when the completion phrase is
+    /// detected or the PRD is complete. On failure the loop continues and the
+    /// command's output is added to the next prompt.
+    #[arg(long = "verify", value_name = "CMD")]
+    pub verify: Option<String>,
+
+    /// Verify command timeout in seconds (0 to disable).
+    #[arg(long = "verify-timeout", default_value = "600")]
+    pub verify_timeout: u64,
+
+    /// Add a section about the previous iteration to each prompt.
+    ///
+    /// Gives the agent the last iteration's exit code, agent error, retries,
+    /// failed verify output and last lines of output.
+    #[arg(long = "iteration-feedback")]
+    pub iteration_feedback: bool,
+
+    /// Header line of the previous-iteration feedback section.
+    #[arg(
+        long = "feedback-header",
+        value_name = "TEXT",
+        default_value = "## Previous iteration"
+    )]
+    pub feedback_header: String,
+
+    /// Lines of the previous iteration's output in the feedback section.
+    #[arg(long = "feedback-lines", value_name = "N", default_value = "20")]
+    pub feedback_lines: usize,
+
+    /// Maximum size of the feedback section in characters (0 for no limit).
+    #[arg(long = "feedback-max-chars", value_name = "N", default_value = "4000")]
+    pub feedback_max_chars: usize,
+
+    /// Command to run before each agent invocation (can be specified multiple times).
+    ///
+    /// Hooks run in order with `sh -c` and receive iteration metadata in
+    /// `WIGGLE_PUPPY_*` environment variables.
+    #[arg(long = "pre-hook", value_name = "CMD", action = clap::ArgAction::Append)]
+    pub pre_hooks: Vec<String>,
+
+    /// Command to run after each agent invocation (can be specified multiple times).
+    #[arg(long = "post-hook", value_name = "CMD", action = clap::ArgAction::Append)]
+    pub post_hooks: Vec<String>,
+
+    /// Timeout for each hook in seconds (0 to disable).
+    #[arg(long = "hook-timeout", default_value = "300")]
+    pub hook_timeout: u64,
+
+    /// What to do when a hook fails: ignore, warn, retry (the iteration) or stop.
+    #[arg(long = "hook-failure", value_name = "POLICY", default_value = "warn")]
+    pub hook_failure: HookFailure,
+
     /// Maximum retry attempts after error/timeout.
     #[arg(long = "max-retries", default_value = "3")]
     pub max_retries: u32,
@@ -104,9 +334,34 @@ pub struct Cli {
     #[arg(long = "circuit-breaker", default_value = "5")]
     pub circuit_breaker: u32,
 
+    /// Stop after N consecutive iterations without progress (0 to disable).
+    ///
+    /// An iteration makes progress if it completes a PRD story, or doesn't
+    /// repeat its previous output (and, with --stall-workspace, changes files).
+    #[arg(long = "stall-threshold", default_value = "0")]
+    pub stall_threshold: u32,
+
+    /// Directory watched for file changes by stall detection.
+    ///
+    /// The whole tree is walked after every iteration, skipping only `.git`,
+    /// `target` and `node_modules`, so point it at the sources rather than a
+    /// directory with large build outputs.
+    #[arg(long = "stall-workspace", value_name = "DIR")]
+    pub stall_workspace: Option<PathBuf>,
+
+    /// Minimum wait in seconds before retrying after a rate-limited error pattern.
+    #[arg(long = "rate-limit-backoff", default_value = "120")]
+    pub rate_limit_backoff: u64,
+
     /// Additional error patterns to detect (can be specified multiple times).
+    ///
+    /// Each pattern is a regex, optionally prefixed with its class:
+    /// `transient:`, `rate-limited:`, `fatal:` or `ignore:` (default transient).
+    /// Transient errors are retried, rate limits wait longer, fatal errors
+    /// stop the run, and lines matching an ignore pattern are exempt from all
+    /// other patterns, including the defaults.
     #[arg(long = "error-pattern", action = clap::ArgAction::Append)]
-    pub error_patterns: Vec<String>,
+    pub error_patterns: Vec<ErrorPattern>,
 
     /// Disable default error pattern detection.
     #[arg(long = "no-error-patterns")]
@@ -114,44 +369,111 @@ pub struct Cli {
 }
 
 impl Cli {
+    /// Read the prompt from stdin if `-` is one of the prompt files.
+    pub fn read_stdin_prompt(&mut self) -> std::io::Result<()> {
+        if !self.prompt_files.iter().any(|path| is_stdin(path)) {
+            return Ok(());
+        }
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        self.stdin_prompt = Some(text);
+        Ok(())
+    }
+
     /// Convert CLI arguments to a Config.
     pub fn to_config(&self) -> wiggle_puppy_core::Config {
         let mut config = wiggle_puppy_core::Config::new()
             .agent_command(&self.agent)
             .agent_args_str(&self.agent_args)
+            .prompt_delivery(self.prompt_delivery)
             .max_iterations(self.max_iterations)
             .delay_secs(self.delay)
             .completion_phrase(&self.completion)
-            .auto_completion_instruction(!self.no_auto_instruction);
+            .completion_nonce(self.completion_nonce)
+            .completion_detectors(self.completion_detectors.clone())
+            .completion_policy(self.completion_policy)
+            .auto_completion_instruction(!self.no_auto_instruction)
+            .prompt_templating(!self.no_templating);
 
-        if let Some(ref path) = self.prompt_file {
-            config = config.prompt_path(path);
+        for path in &self.prompt_files {
+            config = match &self.stdin_prompt {
+                Some(text) if is_stdin(path) => config.add_prompt_text(text),
+                _ => config.add_prompt_path(path),
+            };
         }
 
         if let Some(ref text) = self.prompt {
             config = config.prompt_text(text);
         }
 
+        for (name, value) in &self.vars {
+            config = config.template_var(name, value);
+        }
+
         if let Some(ref path) = self.state {
             config = config.prd_path(path);
         }
 
+        if let Some(ref path) = self.progress {
+            config = config.progress_path(path);
+        }
+
+        if let Some(ref path) = self.checkpoint {
+            config = config
+                .checkpoint_path(path)
+                .resume(self.resume || self.force_resume)
+                .force_resume(self.force_resume);
+        }
+
+        let hook = |command: &String| {
+            Hook::new(command)
+                .timeout_secs(self.hook_timeout)
+                .on_failure(self.hook_failure)
+        };
         config = config
+            .pre_iteration_hooks(self.pre_hooks.iter().map(hook).collect())
+            .post_iteration_hooks(self.post_hooks.iter().map(hook).collect());
+
+        if let Some(ref command) = self.verify {
+            config = config.verify_command(command);
+        }
+
+        config = config
+            .verify_timeout_secs(self.verify_timeout)
+            .iteration_feedback(self.iteration_feedback)
+            .feedback_header(&self.feedback_header)
+            .feedback_output_lines(self.feedback_lines)
+            .feedback_max_chars(self.feedback_max_chars)
             .agent_timeout_secs(self.agent_timeout)
+            .agent_idle_timeout_secs(self.idle_timeout)
+            .agent_kill_grace_secs(self.kill_grace)
+            .usage_limit_wait_secs(self.usage_limit_wait)
+            .max_usage_limit_waits(self.max_usage_limit_waits)
             .max_retries(self.max_retries)
-            .circuit_breaker_threshold(self.circuit_breaker);
+            .rate_limit_backoff_secs(self.rate_limit_backoff)
+            .circuit_breaker_threshold(self.circuit_breaker)
+            .stall_threshold(self.stall_threshold);
+
+        if let Some(ref path) = self.stall_workspace {
+            config = config.stall_workspace(path);
+        }
 
         if self.no_error_patterns {
             config = config.no_error_patterns();
         }
         for pattern in &self.error_patterns {
-            config = config.add_error_pattern(pattern);
+            config = config.add_error_pattern(pattern.clone());
         }
 
         config
     }
 }
 
+/// Whether a prompt file argument stands for stdin.
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
 /// Print the startup header with configuration info.
 fn print_header(cli: &Cli) {
     println!("Wiggle Puppy - Autonomous Agent Runner");
@@ -163,6 +485,18 @@ fn print_header(cli: &Cli) {
         println!("State file: {}", state_path.display());
     }
 
+    if let Some(ref progress_path) = cli.progress {
+        println!("Progress log: {}", progress_path.display());
+    }
+
+    if let Some(ref checkpoint_path) = cli.checkpoint {
+        println!("Checkpoint: {}", checkpoint_path.display());
+    }
+
+    if let Some(ref command) = cli.verify {
+        println!("Verify command: {}", command);
+    }
+
     println!();
 }
 
@@ -219,6 +553,11 @@ impl EventHandler {
                 println!();
             }
 
+            Event::RunResumed { run_id, iteration } => {
+                println!("Resuming run {} after iteration {}", run_id, iteration);
+                println!();
+            }
+
             Event::IterationStarted {
                 iteration,
                 max_iterations,
@@ -290,6 +629,78 @@ impl EventHandler {
                 println!();
             }
 
+            Event::HookStarted { stage, command } => {
+                println!("  Running {} hook: {}", stage, command);
+            }
+
+            Event::HookOutput {
+                stage: _,
+                text,
+                is_stderr,
+            } => {
+                if self.verbose {
+                    if is_stderr {
+                        eprintln!("    {}", text);
+                    } else {
+                        println!("    {}", text);
+                    }
+                }
+            }
+
+            Event::HookFinished {
+                stage,
+                command: _,
+                exit_code,
+                success,
+                timed_out,
+                duration_secs,
+            } => {
+                if timed_out {
+                    eprintln!("  {} hook timed out after {:.1}s", stage, duration_secs);
+                } else if !success {
+                    let code =
+                        exit_code.map_or_else(|| "none".to_string(), |code| code.to_string());
+                    eprintln!(
+                        "  {} hook failed with exit code {} ({:.1}s)",
+                        stage, code, duration_secs
+                    );
+                }
+            }
+
+            Event::VerifyStarted { command } => {
+                println!("  Verifying: {}", command);
+            }
+
+            Event::VerifyFinished {
+                command: _,
+                exit_code,
+                success,
+                timed_out,
+                output_tail,
+                duration_secs,
+            } => {
+                if success {
+                    println!("  Verify passed ({:.1}s)", duration_secs);
+                } else {
+                    if timed_out {
+                        eprintln!("  Verify timed out after {:.1}s", duration_secs);
+                    } else {
+                        let code =
+                            exit_code.map_or_else(|| "none".to_string(), |code| code.to_string());
+                        eprintln!(
+                            "  Verify failed with exit code {} ({:.1}s)",
+                            code, duration_secs
+                        );
+                    }
+                    let lines: Vec<&str> = output_tail.lines().collect();
+                    let start = lines.len().saturating_sub(VERIFY_DISPLAY_LINES);
+                    for line in &lines[start..] {
+                        eprintln!("    {}", line);
+                    }
+                    eprintln!("  Continuing with the verify output added to the prompt");
+                }
+            }
+
             Event::PrdUpdated { completed, total } => {
                 println!("  PRD progress: {}/{} stories complete", completed, total);
             }
@@ -301,6 +712,27 @@ impl EventHandler {
                 println!("  Story completed: {} - {}", story_id, story_title);
             }
 
+            Event::StoryReopened {
+                story_id,
+                story_title,
+            } => {
+                println!("  Story reopened: {} - {}", story_id, story_title);
+            }
+
+            Event::StoryAdded {
+                story_id,
+                story_title,
+            } => {
+                println!("  Story added: {} - {}", story_id, story_title);
+            }
+
+            Event::StoryRemoved {
+                story_id,
+                story_title,
+            } => {
+                println!("  Story removed: {} - {}", story_id, story_title);
+            }
+
             Event::Progress { message } => {
                 println!("  {}", message);
             }
@@ -313,14 +745,48 @@ impl EventHandler {
                 eprintln!("  Error: {}", message);
             }
 
-            Event::AgentErrorDetected { pattern } => {
-                eprintln!("  Error pattern detected: {}", pattern);
+            Event::AgentErrorDetected {
+                pattern,
+                class,
+                line,
+                line_number,
+            } => {
+                eprintln!(
+                    "  {} error pattern detected on line {}: {}",
+                    class, line_number, pattern
+                );
+                eprintln!("    {}", line);
             }
 
             Event::AgentTimeout { timeout_secs } => {
                 eprintln!("  Agent timed out after {} seconds", timeout_secs);
             }
 
+            Event::AgentIdleTimeout { idle_secs } => {
+                eprintln!("  Agent produced no output for {} seconds", idle_secs);
+            }
+
+            Event::RateLimited { resume_at } => {
+                println!(
+                    "  Usage limit reached; sleeping until {}",
+                    resume_at
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+
+            Event::Paused { agent_suspended } => {
+                if agent_suspended {
+                    println!("  Paused (agent suspended)");
+                } else {
+                    println!("  Paused before the next iteration");
+                }
+            }
+
+            Event::Resumed { paused_secs } => {
+                println!("  Resumed after {:.1}s", paused_secs);
+            }
+
             Event::RetryScheduled {
                 backoff_secs,
                 attempt,
@@ -332,6 +798,15 @@ impl EventHandler {
                 );
             }
 
+            Event::ConfigChanged { changes } => {
+                for change in changes {
+                    println!(
+                        "  Config changed: {} {} -> {}",
+                        change.setting, change.old, change.new
+                    );
+                }
+            }
+
             Event::Completed { iterations, reason } => {
                 println!("======================================");
                 println!(
@@ -356,11 +831,16 @@ impl EventHandler {
 }
 
 /// Format a completion reason for display.
-fn format_completion_reason(reason: &CompletionReason) -> &'static str {
+fn format_completion_reason(reason: &CompletionReason) -> String {
     match reason {
-        CompletionReason::AllStoriesComplete => "All stories in PRD are complete",
-        CompletionReason::CompletionPhraseDetected => "Completion phrase detected in agent output",
-        CompletionReason::Both => "All stories complete and completion phrase detected",
+        CompletionReason::AllStoriesComplete => "All stories in PRD are complete".to_string(),
+        CompletionReason::CompletionPhraseDetected => {
+            "Completion phrase detected in agent output".to_string()
+        }
+        CompletionReason::DetectorMatched { detector } => {
+            format!("Completion detector matched: {}", detector)
+        }
+        CompletionReason::Both => "All stories complete and completion phrase detected".to_string(),
     }
 }
 
@@ -378,47 +858,211 @@ fn format_stop_reason(reason: &StopReason) -> String {
                 consecutive_failures
             )
         }
+        StopReason::Stalled {
+            iterations_without_progress,
+        } => format!(
+            "No progress in {} consecutive iterations",
+            iterations_without_progress
+        ),
+        StopReason::UsageLimitPersisted { waits } => {
+            format!("Usage limit still reached after {} waits", waits)
+        }
+        StopReason::AgentBlocked { reason } => format!("Agent is blocked: {}", reason),
+        StopReason::AwaitingInput { question } => format!("Agent needs input: {}", question),
+    }
+}
+
+/// A JSON value tagged with the event schema version.
+#[derive(Serialize)]
+struct Versioned<T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    value: T,
+}
+
+impl<T> Versioned<T> {
+    fn new(value: T) -> Self {
+        Self {
+            schema_version: EVENT_SCHEMA_VERSION,
+            value,
+        }
+    }
+}
+
+/// The final summary object written after the last event.
+#[derive(Serialize)]
+struct Summary<'a> {
+    /// Always `"summary"`, so JSON lines readers can tell it from events.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    /// The run id stamped on the run's events.
+    run_id: Uuid,
+    /// The run outcome, flattened in as `outcome`, `iterations` and `reason`.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    outcome: Option<&'a Outcome>,
+    /// The error that ended the run, if it failed before finishing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The process exit code.
+    exit_code: u8,
+}
+
+impl<'a> Summary<'a> {
+    fn new(run_id: Uuid, result: &'a wiggle_puppy_core::Result<Outcome>) -> Self {
+        Self {
+            kind: "summary",
+            run_id,
+            outcome: result.as_ref().ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            exit_code: exit_code(result),
+        }
+    }
+}
+
+/// The document written by `--output-format json`.
+#[derive(Serialize)]
+struct Report<'a> {
+    events: &'a [EventEnvelope],
+    summary: Summary<'a>,
+}
+
+/// Write a value as a single line of JSON to stdout.
+fn print_json_line(value: &impl Serialize) {
+    let mut stdout = std::io::stdout().lock();
+    match serde_json::to_string(value) {
+        Ok(json) => {
+            let _ = writeln!(stdout, "{}", json);
+            let _ = stdout.flush();
+        }
+        Err(e) => eprintln!("Failed to serialize output: {}", e),
     }
 }
 
 /// Consume events from the receiver and handle them.
-async fn handle_events(mut receiver: EventReceiver, verbose: bool) {
+///
+/// In `json` mode the events are collected and returned so they can be
+/// written with the summary once the run ends.
+async fn handle_events(
+    mut receiver: EventReceiver,
+    verbose: bool,
+    format: OutputFormat,
+) -> Vec<EventEnvelope> {
     let mut handler = EventHandler::new(verbose);
+    let mut events = Vec::new();
 
-    while let Some(event) = receiver.recv().await {
-        handler.handle(event);
+    while let Some(envelope) = receiver.recv().await {
+        match format {
+            OutputFormat::Text => handler.handle(envelope.event),
+            OutputFormat::Json => events.push(envelope),
+            OutputFormat::Jsonl => print_json_line(&Versioned::new(&envelope)),
+        }
     }
+
+    events
+}
+
+/// The process exit code for a run's result.
+fn exit_code(result: &wiggle_puppy_core::Result<Outcome>) -> u8 {
+    match result {
+        Ok(Outcome::Completed { .. }) => 0,
+        Ok(Outcome::Stopped {
+            reason: StopReason::Cancelled,
+            ..
+        }) => EXIT_CANCELLED,
+        Ok(Outcome::Stopped {
+            reason: StopReason::AgentBlocked { .. },
+            ..
+        }) => EXIT_AGENT_BLOCKED,
+        Ok(Outcome::Stopped {
+            reason: StopReason::AwaitingInput { .. },
+            ..
+        }) => EXIT_AWAITING_INPUT,
+        Ok(Outcome::Stopped { .. }) | Err(_) => 1,
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Two-stage shutdown: the first signal stops after the current iteration,
+/// the second cancels immediately and terminates the agent.
+async fn handle_signals(handle: RunnerHandle) {
+    if shutdown_signal().await.is_err() {
+        return;
+    }
+    eprintln!();
+    eprintln!("Stopping after the current iteration (press Ctrl-C again to cancel now)...");
+    handle.stop_after_iteration();
+
+    if shutdown_signal().await.is_err() {
+        return;
+    }
+    eprintln!();
+    eprintln!("Cancelling and terminating the agent...");
+    handle.cancel();
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
     let verbose = cli.verbose;
+    let format = cli.output_format;
+
+    if let Err(e) = cli.read_stdin_prompt() {
+        eprintln!("Failed to read prompt from stdin: {}", e);
+        return ExitCode::FAILURE;
+    }
 
-    // Print header and PRD summary
-    print_header(&cli);
-    print_prd_summary(&cli);
+    // Print header and PRD summary; JSON output keeps stdout machine-readable
+    if format == OutputFormat::Text {
+        print_header(&cli);
+        print_prd_summary(&cli);
+    }
 
     // Create runner
     let config = cli.to_config();
-    let (runner, receiver, _handle) = Runner::new(config);
+    let (runner, receiver, handle) = Runner::new(config);
 
-    // Spawn event handler task
-    let event_task = tokio::spawn(handle_events(receiver, verbose));
+    // Spawn event handler and signal handler tasks
+    let event_task = tokio::spawn(handle_events(receiver, verbose, format));
+    let signal_task = tokio::spawn(handle_signals(handle));
 
-    // Run the main loop
+    // Run the main loop, then drop the runner to close the event channel.
+    // The run id is read afterwards since a resumed run takes the checkpoint's.
     let outcome = runner.run().await;
+    let run_id = runner.run_id();
+    drop(runner);
+    signal_task.abort();
 
     // Wait for event handler to finish processing
-    let _ = event_task.await;
-
-    // Return appropriate exit code
-    match outcome {
-        Ok(Outcome::Completed { .. }) => ExitCode::SUCCESS,
-        Ok(Outcome::Stopped { .. }) => ExitCode::FAILURE,
-        Err(e) => {
-            eprintln!("Fatal error: {}", e);
-            ExitCode::FAILURE
-        }
+    let events = event_task.await.unwrap_or_default();
+
+    if let Err(ref e) = outcome {
+        eprintln!("Fatal error: {}", e);
     }
+
+    match format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => print_json_line(&Versioned::new(Report {
+            events: &events,
+            summary: Summary::new(run_id, &outcome),
+        })),
+        OutputFormat::Jsonl => print_json_line(&Versioned::new(Summary::new(run_id, &outcome))),
+    }
+
+    ExitCode::from(exit_code(&outcome))
 }
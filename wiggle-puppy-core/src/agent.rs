@@ -6,11 +6,242 @@
 
 use crate::error::{Error, Result};
 use crate::event::{Event, EventSender};
+use crate::pattern::{ErrorClass, ErrorPattern};
+use crate::process::{isolate, kill_tree, terminate, ProcessGroup};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use regex::Regex;
+use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
-use tokio::time::timeout;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// Largest prompt (in bytes) that will be passed on the command line.
+///
+/// Linux limits a single argument to 128 KiB (`MAX_ARG_STRLEN`), and the whole
+/// argv plus environment to `ARG_MAX`. Prompts above this size are switched to
+/// stdin delivery to stay well clear of both limits.
+const MAX_ARG_PROMPT_BYTES: usize = 100 * 1024;
+
+/// Placeholder in agent arguments replaced by the prompt text.
+const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+/// Placeholder in agent arguments replaced by the path of a temp file holding the prompt.
+const PROMPT_FILE_PLACEHOLDER: &str = "{prompt_file}";
+
+/// Default wait in seconds after a usage limit message that carries no reset time.
+pub(crate) const DEFAULT_USAGE_LIMIT_WAIT_SECS: u64 = 3600;
+
+/// Extra seconds to wait past a parsed reset time, to absorb clock skew.
+const USAGE_LIMIT_GRACE_SECS: i64 = 60;
+
+/// Usage limit messages printed by the claude CLI.
+static USAGE_LIMIT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(usage limit reached|\b\d+-hour limit reached|weekly limit reached|you've hit your (usage )?limit)",
+    )
+    .expect("usage limit regex is valid")
+});
+
+/// Reset time as a unix timestamp, e.g. `Claude AI usage limit reached|1749924000`.
+static RESET_EPOCH_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"limit reached\|(\d{9,})").expect("reset epoch regex is valid"));
+
+/// Reset time of day, e.g. `resets 3pm`, `reset at 3:30pm`, `resets 15:00`.
+static RESET_TIME_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\bresets?(?: at)? (\d{1,2})(?::(\d{2}))?\s*(am|pm)?\b")
+        .expect("reset time regex is valid")
+});
+
+/// A usage limit message found in agent output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct UsageLimit {
+    /// When the quota resets, if the message said.
+    resets_at: Option<DateTime<Utc>>,
+}
+
+/// Detect a usage limit message and parse its reset time relative to `now`.
+///
+/// Reset times of day are interpreted in the local timezone as their next
+/// occurrence; a timezone name printed after the time is not consulted.
+fn parse_usage_limit(line: &str, now: DateTime<Local>) -> Option<UsageLimit> {
+    if !USAGE_LIMIT_RE.is_match(line) {
+        return None;
+    }
+
+    if let Some(caps) = RESET_EPOCH_RE.captures(line) {
+        let resets_at = caps[1]
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single());
+        return Some(UsageLimit { resets_at });
+    }
+
+    let resets_at = RESET_TIME_RE.captures(line).and_then(|caps| {
+        let mut hour: u32 = caps[1].parse().ok()?;
+        let minute: u32 = caps.get(2).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        match caps
+            .get(3)
+            .map(|m| m.as_str().to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("am") if hour == 12 => hour = 0,
+            Some("pm") if hour < 12 => hour += 12,
+            _ => {}
+        }
+        let today = now.date_naive().and_hms_opt(hour, minute, 0)?;
+        let mut reset = Local.from_local_datetime(&today).earliest()?;
+        if reset <= now {
+            reset += chrono::Duration::days(1);
+        }
+        Some(reset.with_timezone(&Utc))
+    });
+
+    Some(UsageLimit { resets_at })
+}
+
+/// Something in a line of agent output that ends the run early.
+#[derive(Debug)]
+enum Alert<'a> {
+    /// The agent hit its usage quota; resume at the given time.
+    UsageLimit(DateTime<Utc>),
+    /// A configured error pattern matched.
+    ErrorPattern(&'a ErrorPattern),
+}
+
+/// Default seconds between SIGTERM and SIGKILL when cancelling the agent.
+pub(crate) const DEFAULT_KILL_GRACE_SECS: u64 = 5;
+
+/// Counter used to give each prompt temp file a unique name.
+static PROMPT_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Names tried before giving up when prompt temp files already exist.
+const PROMPT_FILE_ATTEMPTS: u32 = 16;
+
+/// How the prompt is handed to the agent process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PromptDelivery {
+    /// Pass the prompt as the final command-line argument.
+    #[default]
+    Argument,
+    /// Write the prompt to the agent's stdin, then close it.
+    Stdin,
+    /// Write the prompt to a temp file and pass its path as the final argument.
+    TempFile,
+    /// Replace `{prompt}` and `{prompt_file}` placeholders inside the agent arguments.
+    Placeholder,
+}
+
+impl std::fmt::Display for PromptDelivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptDelivery::Argument => write!(f, "arg"),
+            PromptDelivery::Stdin => write!(f, "stdin"),
+            PromptDelivery::TempFile => write!(f, "file"),
+            PromptDelivery::Placeholder => write!(f, "placeholder"),
+        }
+    }
+}
+
+impl std::str::FromStr for PromptDelivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "arg" | "argument" => Ok(PromptDelivery::Argument),
+            "stdin" => Ok(PromptDelivery::Stdin),
+            "file" | "temp-file" => Ok(PromptDelivery::TempFile),
+            "placeholder" => Ok(PromptDelivery::Placeholder),
+            other => Err(format!(
+                "unknown prompt delivery '{}' (expected arg, stdin, file or placeholder)",
+                other
+            )),
+        }
+    }
+}
+
+/// A temp file holding the prompt, removed when dropped.
+#[derive(Debug)]
+struct PromptFile {
+    path: PathBuf,
+}
+
+impl PromptFile {
+    /// Write the prompt to a fresh file in the system temp directory.
+    ///
+    /// The file is created exclusively, readable by the owner only, so a
+    /// file or symlink planted at the same path is never written through;
+    /// the next name is tried instead.
+    async fn create(prompt: &str) -> Result<Self> {
+        let mut attempts = 0;
+        let (path, mut file) = loop {
+            let path = std::env::temp_dir().join(format!(
+                "wiggle-puppy-prompt-{}-{}.md",
+                std::process::id(),
+                PROMPT_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let mut options = tokio::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+            match options.open(&path).await {
+                Ok(file) => break (path, file),
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::AlreadyExists
+                        && attempts + 1 < PROMPT_FILE_ATTEMPTS =>
+                {
+                    attempts += 1;
+                }
+                Err(e) => return Err(prompt_file_error(&path, e)),
+            }
+        };
+
+        // Owned from here on, so a failed write still removes the file
+        let prompt_file = Self { path };
+        file.write_all(prompt.as_bytes())
+            .await
+            .map_err(|e| prompt_file_error(&prompt_file.path, e))?;
+        file.flush()
+            .await
+            .map_err(|e| prompt_file_error(&prompt_file.path, e))?;
+        Ok(prompt_file)
+    }
+
+    /// The path as a command-line argument.
+    fn arg(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+/// Error for a prompt temp file that couldn't be created or written.
+fn prompt_file_error(path: &std::path::Path, e: std::io::Error) -> Error {
+    Error::AgentError {
+        message: format!("failed to write prompt file '{}': {}", path.display(), e),
+    }
+}
+
+impl Drop for PromptFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The resolved way a single agent run receives its prompt.
+#[derive(Debug)]
+struct Invocation {
+    /// Final argument list for the agent command.
+    args: Vec<String>,
+    /// Prompt text to write to stdin, if delivering via stdin.
+    stdin: Option<String>,
+    /// Temp file backing `{prompt_file}` or file delivery; kept alive for the run.
+    _prompt_file: Option<PromptFile>,
+    /// Set when an oversized prompt forced a switch to stdin delivery.
+    fallback_notice: Option<String>,
+}
 
 /// An agent that can be spawned to execute tasks.
 ///
@@ -22,10 +253,22 @@ pub struct Agent {
     command: String,
     /// Arguments to pass to the command.
     args: Vec<String>,
-    /// Patterns to detect in output that indicate an error.
-    error_patterns: Vec<String>,
-    /// Timeout in seconds for the agent process.
+    /// Classified patterns to detect in output that indicate an error.
+    error_patterns: Vec<ErrorPattern>,
+    /// Timeout in seconds for the whole agent run.
     timeout_secs: u64,
+    /// Seconds without any output before the agent is considered hung (0 = disabled).
+    idle_timeout_secs: u64,
+    /// Seconds to wait after a usage limit message that carries no reset time.
+    usage_limit_wait_secs: u64,
+    /// How the prompt is handed to the agent process.
+    prompt_delivery: PromptDelivery,
+    /// Cancels the running agent when triggered.
+    cancel: CancellationToken,
+    /// Seconds between SIGTERM and SIGKILL when cancelling.
+    kill_grace_secs: u64,
+    /// Suspends the running agent (SIGSTOP) while set to `true`.
+    suspension: Option<watch::Receiver<bool>>,
 }
 
 impl Agent {
@@ -35,25 +278,25 @@ impl Agent {
     ///
     /// * `command` - The command to run (e.g., "claude", "aider").
     /// * `args` - Arguments to pass to the command.
-    /// * `error_patterns` - Patterns to detect in output that indicate an error.
-    /// * `timeout_secs` - Timeout in seconds for the agent process.
+    /// * `error_patterns` - Classified patterns to detect in output that indicate an error.
+    /// * `timeout_secs` - Timeout in seconds for the whole agent run.
     ///
     /// # Examples
     ///
     /// ```
-    /// use wiggle_puppy_core::Agent;
+    /// use wiggle_puppy_core::{Agent, ErrorClass, ErrorPattern};
     ///
     /// let agent = Agent::new(
     ///     "claude",
     ///     vec!["-p".to_string()],
-    ///     vec!["FATAL ERROR".to_string()],
+    ///     vec![ErrorPattern::literal("FATAL ERROR", ErrorClass::Fatal)],
     ///     300,
     /// );
     /// ```
     pub fn new(
         command: impl Into<String>,
         args: Vec<String>,
-        error_patterns: Vec<String>,
+        error_patterns: Vec<ErrorPattern>,
         timeout_secs: u64,
     ) -> Self {
         Self {
@@ -61,9 +304,58 @@ impl Agent {
             args,
             error_patterns,
             timeout_secs,
+            idle_timeout_secs: 0,
+            usage_limit_wait_secs: DEFAULT_USAGE_LIMIT_WAIT_SECS,
+            prompt_delivery: PromptDelivery::default(),
+            cancel: CancellationToken::new(),
+            kill_grace_secs: DEFAULT_KILL_GRACE_SECS,
+            suspension: None,
         }
     }
 
+    /// Set the token that cancels a running agent.
+    ///
+    /// On cancellation the agent receives SIGTERM, then SIGKILL if it is still
+    /// running after the kill grace period.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Set the seconds between SIGTERM and SIGKILL when cancelling.
+    pub fn kill_grace_secs(mut self, secs: u64) -> Self {
+        self.kill_grace_secs = secs;
+        self
+    }
+
+    /// Set the channel that suspends and resumes a running agent.
+    ///
+    /// While the value is `true` the agent's process group is stopped with
+    /// SIGSTOP; setting it back to `false` sends SIGCONT. Time spent suspended
+    /// does not count towards the run or idle timeouts. Has no effect off Unix.
+    pub fn suspension(mut self, suspended: watch::Receiver<bool>) -> Self {
+        self.suspension = Some(suspended);
+        self
+    }
+
+    /// Set the idle timeout: kill the agent after this many seconds without output (0 = disabled).
+    pub fn idle_timeout_secs(mut self, secs: u64) -> Self {
+        self.idle_timeout_secs = secs;
+        self
+    }
+
+    /// Set the wait in seconds after a usage limit message that carries no reset time.
+    pub fn usage_limit_wait_secs(mut self, secs: u64) -> Self {
+        self.usage_limit_wait_secs = secs;
+        self
+    }
+
+    /// Set how the prompt is handed to the agent process.
+    pub fn prompt_delivery(mut self, delivery: PromptDelivery) -> Self {
+        self.prompt_delivery = delivery;
+        self
+    }
+
     /// Get the command this agent will run.
     pub fn command(&self) -> &str {
         &self.command
@@ -74,10 +366,141 @@ impl Agent {
         &self.args
     }
 
+    /// Check a line of output for a usage limit message or error pattern.
+    ///
+    /// Usage limits take precedence so they are waited out rather than retried.
+    /// Lines matching an `Ignore` pattern are never alerts. A reset time that
+    /// is not in the future (say, an old message printed by a test) falls back
+    /// to the usage limit wait, so the runner never retries without waiting.
+    fn inspect_line(&self, text: &str) -> Option<Alert<'_>> {
+        if self.is_ignored(text) {
+            return None;
+        }
+        if let Some(limit) = parse_usage_limit(text, Local::now()) {
+            let now = Utc::now();
+            let resume_at = match limit.resets_at {
+                Some(reset) if reset > now => {
+                    reset + chrono::Duration::seconds(USAGE_LIMIT_GRACE_SECS)
+                }
+                _ => now + chrono::Duration::seconds(self.usage_limit_wait_secs as i64),
+            };
+            return Some(Alert::UsageLimit(resume_at));
+        }
+        self.match_error_pattern(text).map(Alert::ErrorPattern)
+    }
+
+    /// Return the first configured error pattern classifying a line of output.
+    ///
+    /// `Ignore` patterns are skipped; `inspect_line` has already checked them.
+    fn match_error_pattern(&self, text: &str) -> Option<&ErrorPattern> {
+        self.error_patterns
+            .iter()
+            .find(|pattern| pattern.class() != ErrorClass::Ignore && pattern.is_match(text))
+    }
+
+    /// Check whether an `Ignore` pattern exempts a line of output.
+    fn is_ignored(&self, text: &str) -> bool {
+        self.error_patterns
+            .iter()
+            .any(|pattern| pattern.class() == ErrorClass::Ignore && pattern.is_match(text))
+    }
+
+    /// Resolve the argument list and stdin payload for the given prompt.
+    ///
+    /// Prompts larger than `MAX_ARG_PROMPT_BYTES` that would otherwise land in
+    /// argv are delivered via stdin instead; `{prompt}` arguments are dropped
+    /// in that case since they cannot carry the text.
+    async fn invocation(&self, prompt: &str) -> Result<Invocation> {
+        let oversized = prompt.len() > MAX_ARG_PROMPT_BYTES;
+        let fallback_notice = || {
+            Some(format!(
+                "prompt is {} bytes, too large for a command-line argument; delivering via stdin",
+                prompt.len()
+            ))
+        };
+
+        match self.prompt_delivery {
+            PromptDelivery::Argument if oversized => Ok(Invocation {
+                args: self.args.clone(),
+                stdin: Some(prompt.to_string()),
+                _prompt_file: None,
+                fallback_notice: fallback_notice(),
+            }),
+            PromptDelivery::Argument => {
+                let mut args = self.args.clone();
+                args.push(prompt.to_string());
+                Ok(Invocation {
+                    args,
+                    stdin: None,
+                    _prompt_file: None,
+                    fallback_notice: None,
+                })
+            }
+            PromptDelivery::Stdin => Ok(Invocation {
+                args: self.args.clone(),
+                stdin: Some(prompt.to_string()),
+                _prompt_file: None,
+                fallback_notice: None,
+            }),
+            PromptDelivery::TempFile => {
+                let file = PromptFile::create(prompt).await?;
+                let mut args = self.args.clone();
+                args.push(file.arg());
+                Ok(Invocation {
+                    args,
+                    stdin: None,
+                    _prompt_file: Some(file),
+                    fallback_notice: None,
+                })
+            }
+            PromptDelivery::Placeholder => {
+                let uses_prompt = self.args.iter().any(|a| a.contains(PROMPT_PLACEHOLDER));
+                let uses_file = self
+                    .args
+                    .iter()
+                    .any(|a| a.contains(PROMPT_FILE_PLACEHOLDER));
+                if !uses_prompt && !uses_file {
+                    return Err(Error::config_error(format!(
+                        "prompt delivery 'placeholder' requires {} or {} in the agent arguments",
+                        PROMPT_PLACEHOLDER, PROMPT_FILE_PLACEHOLDER
+                    )));
+                }
+
+                let file = if uses_file {
+                    Some(PromptFile::create(prompt).await?)
+                } else {
+                    None
+                };
+                let to_stdin = uses_prompt && oversized;
+
+                let args = self
+                    .args
+                    .iter()
+                    .filter(|a| !(to_stdin && a.contains(PROMPT_PLACEHOLDER)))
+                    .map(|a| {
+                        let mut arg = a.clone();
+                        if let Some(file) = &file {
+                            arg = arg.replace(PROMPT_FILE_PLACEHOLDER, &file.arg());
+                        }
+                        arg.replace(PROMPT_PLACEHOLDER, prompt)
+                    })
+                    .collect();
+
+                Ok(Invocation {
+                    args,
+                    stdin: to_stdin.then(|| prompt.to_string()),
+                    _prompt_file: file,
+                    fallback_notice: if to_stdin { fallback_notice() } else { None },
+                })
+            }
+        }
+    }
+
     /// Run the agent with the given prompt.
     ///
-    /// Spawns the agent process, passes the prompt as the final argument,
-    /// and streams stdout/stderr through the provided event sender.
+    /// Spawns the agent process, hands it the prompt according to the
+    /// configured `PromptDelivery`, and streams stdout/stderr through the
+    /// provided event sender.
     ///
     /// # Arguments
     ///
@@ -92,12 +515,26 @@ impl Agent {
     ///
     /// Returns `Error::AgentNotFound` if the command cannot be found.
     /// Returns `Error::AgentError` if the process fails to spawn or run.
+    /// Returns `Error::Cancelled` if the cancellation token fires during the run.
+    /// Returns `Error::UsageLimitReached` as soon as a usage limit message appears.
+    /// Returns `Error::AgentErrorDetected` as soon as an error pattern matches a line.
+    /// Returns `Error::AgentTimeout` if the whole run exceeds the timeout.
+    /// Returns `Error::AgentIdleTimeout` if the agent goes quiet for longer than the idle timeout.
+    /// Returns `Error::ConfigError` if placeholder delivery is used without placeholders.
     pub async fn run(&self, prompt: &str, events: &EventSender) -> Result<AgentOutput> {
         let start = Instant::now();
 
+        let invocation = self.invocation(prompt).await?;
+        if let Some(notice) = &invocation.fallback_notice {
+            let _ = events.send(Event::warning(notice)).await;
+        }
+
         let mut cmd = Command::new(&self.command);
-        cmd.args(&self.args);
-        cmd.arg(prompt);
+        cmd.args(&invocation.args);
+        isolate(&mut cmd);
+        if invocation.stdin.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
 
@@ -112,6 +549,19 @@ impl Agent {
                 }
             }
         })?;
+        let mut group = ProcessGroup::of(&child);
+
+        if let Some(input) = invocation.stdin {
+            let mut stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| Error::agent_error("failed to capture stdin"))?;
+            // Write concurrently with the output readers so a large prompt
+            // can't deadlock against a full stdout pipe. Dropping stdin closes it.
+            tokio::spawn(async move {
+                let _ = stdin.write_all(input.as_bytes()).await;
+            });
+        }
 
         let stdout = child
             .stdout
@@ -124,103 +574,164 @@ impl Agent {
 
         let mut stdout_reader = BufReader::new(stdout).lines();
         let mut stderr_reader = BufReader::new(stderr).lines();
+        let mut stdout_done = false;
+        let mut stderr_done = false;
 
         let mut stdout_lines = Vec::new();
         let mut stderr_lines = Vec::new();
         let mut combined_lines = Vec::new();
 
-        // Track error patterns detected during streaming
-        let mut detected_error: Option<String> = None;
+        // Alert raised during streaming, with the line and its 1-based number
+        let mut alert: Option<(Alert<'_>, String, usize)> = None;
+
+        // The run deadline covers streaming as well as the final wait, so an
+        // agent that hangs mid-output or holds its pipes open still times out.
+        let mut deadline = tokio::time::Instant::now() + Duration::from_secs(self.timeout_secs);
+        let idle_timeout =
+            (self.idle_timeout_secs > 0).then(|| Duration::from_secs(self.idle_timeout_secs));
+        let mut last_output = tokio::time::Instant::now();
+
+        let mut suspension = self.suspension.clone();
+        let mut suspended_since: Option<tokio::time::Instant> = None;
+
+        // Read stdout and stderr concurrently, then wait for the process to exit
+        let status = loop {
+            // Follow suspend/resume requests, pausing the timeouts while stopped
+            let suspend_requested = suspension
+                .as_mut()
+                .is_some_and(|suspended| *suspended.borrow_and_update());
+            match (suspend_requested, suspended_since) {
+                (true, None) => {
+                    #[cfg(unix)]
+                    group.signal(libc::SIGSTOP);
+                    suspended_since = Some(tokio::time::Instant::now());
+                    let _ = events
+                        .send(Event::Paused {
+                            agent_suspended: true,
+                        })
+                        .await;
+                }
+                (false, Some(since)) => {
+                    #[cfg(unix)]
+                    group.signal(libc::SIGCONT);
+                    let paused_for = since.elapsed();
+                    deadline += paused_for;
+                    last_output += paused_for;
+                    suspended_since = None;
+                    let _ = events
+                        .send(Event::Resumed {
+                            paused_secs: paused_for.as_secs_f64(),
+                        })
+                        .await;
+                }
+                _ => {}
+            }
+            let idle_deadline = idle_timeout.map_or(deadline, |idle| last_output + idle);
 
-        // Read stdout and stderr concurrently
-        loop {
             tokio::select! {
-                line = stdout_reader.next_line() => {
+                line = stdout_reader.next_line(), if !stdout_done => {
                     match line {
                         Ok(Some(text)) => {
+                            last_output = tokio::time::Instant::now();
                             stdout_lines.push(text.clone());
                             combined_lines.push(text.clone());
                             let _ = events.send(Event::agent_output(&text)).await;
-                            // Check for error patterns
-                            for pattern in &self.error_patterns {
-                                if text.contains(pattern) {
-                                    detected_error = Some(pattern.clone());
-                                }
+                            if let Some(found) = self.inspect_line(&text) {
+                                alert = Some((found, text, combined_lines.len()));
                             }
                         }
-                        Ok(None) => {
-                            // stdout closed, but stderr might still have data
-                            // Continue reading stderr only
-                            while let Ok(Some(text)) = stderr_reader.next_line().await {
-                                stderr_lines.push(text.clone());
-                                combined_lines.push(text.clone());
-                                let _ = events.send(Event::agent_stderr(&text)).await;
-                                // Check for error patterns in stderr
-                                for pattern in &self.error_patterns {
-                                    if text.contains(pattern) {
-                                        detected_error = Some(pattern.clone());
-                                    }
-                                }
-                            }
-                            break;
-                        }
+                        Ok(None) => stdout_done = true,
                         Err(e) => {
                             let _ = events.send(Event::error(format!("error reading stdout: {}", e))).await;
-                            break;
+                            stdout_done = true;
                         }
                     }
                 }
-                line = stderr_reader.next_line() => {
+                line = stderr_reader.next_line(), if !stderr_done => {
                     match line {
                         Ok(Some(text)) => {
+                            last_output = tokio::time::Instant::now();
                             stderr_lines.push(text.clone());
                             combined_lines.push(text.clone());
                             let _ = events.send(Event::agent_stderr(&text)).await;
-                            // Check for error patterns
-                            for pattern in &self.error_patterns {
-                                if text.contains(pattern) {
-                                    detected_error = Some(pattern.clone());
-                                }
+                            if let Some(found) = self.inspect_line(&text) {
+                                alert = Some((found, text, combined_lines.len()));
                             }
                         }
-                        Ok(None) => {
-                            // stderr closed, continue with stdout only
-                        }
+                        Ok(None) => stderr_done = true,
                         Err(e) => {
                             let _ = events.send(Event::error(format!("error reading stderr: {}", e))).await;
+                            stderr_done = true;
                         }
                     }
                 }
+                status = child.wait(), if stdout_done && stderr_done => {
+                    match status {
+                        Ok(status) => {
+                            group.release();
+                            break status;
+                        }
+                        Err(e) => {
+                            return Err(Error::AgentError {
+                                message: format!("wait failed: {}", e),
+                            })
+                        }
+                    }
+                }
+                changed = suspension_changed(&mut suspension), if suspension.is_some() => {
+                    if changed.is_err() {
+                        // Nobody can resume us any more, so stop listening
+                        suspension = None;
+                    }
+                }
+                _ = self.cancel.cancelled() => {
+                    terminate(&mut child, &mut group, Duration::from_secs(self.kill_grace_secs)).await;
+                    return Err(Error::Cancelled);
+                }
+                _ = tokio::time::sleep_until(deadline), if suspended_since.is_none() => {
+                    kill_tree(&mut child, &mut group).await;
+                    let _ = events
+                        .send(Event::AgentTimeout {
+                            timeout_secs: self.timeout_secs,
+                        })
+                        .await;
+                    return Err(Error::agent_timeout(self.timeout_secs));
+                }
+                _ = tokio::time::sleep_until(idle_deadline), if idle_timeout.is_some() && suspended_since.is_none() => {
+                    kill_tree(&mut child, &mut group).await;
+                    let _ = events
+                        .send(Event::AgentIdleTimeout {
+                            idle_secs: self.idle_timeout_secs,
+                        })
+                        .await;
+                    return Err(Error::agent_idle_timeout(self.idle_timeout_secs));
+                }
             }
-        }
 
-        // Handle detected error pattern
-        if let Some(pattern) = detected_error {
-            let _ = child.kill().await;
-            let _ = events
-                .send(Event::AgentErrorDetected {
-                    pattern: pattern.clone(),
-                })
-                .await;
-            return Err(Error::agent_error_detected(pattern));
-        }
-
-        // Wait for process with timeout
-        let status = match timeout(Duration::from_secs(self.timeout_secs), child.wait()).await {
-            Ok(Ok(status)) => status,
-            Ok(Err(e)) => {
-                return Err(Error::AgentError {
-                    message: format!("wait failed: {}", e),
-                })
-            }
-            Err(_) => {
-                let _ = child.kill().await;
-                let _ = events
-                    .send(Event::AgentTimeout {
-                        timeout_secs: self.timeout_secs,
-                    })
-                    .await;
-                return Err(Error::agent_timeout(self.timeout_secs));
+            // Abort as soon as a usage limit or error pattern shows up rather
+            // than waiting for the agent to exit on its own
+            match alert.take() {
+                Some((Alert::UsageLimit(resume_at), _, _)) => {
+                    kill_tree(&mut child, &mut group).await;
+                    let _ = events.send(Event::RateLimited { resume_at }).await;
+                    return Err(Error::UsageLimitReached { resume_at });
+                }
+                Some((Alert::ErrorPattern(pattern), line, line_number)) => {
+                    kill_tree(&mut child, &mut group).await;
+                    let _ = events
+                        .send(Event::AgentErrorDetected {
+                            pattern: pattern.as_str().to_string(),
+                            class: pattern.class(),
+                            line,
+                            line_number,
+                        })
+                        .await;
+                    return Err(Error::agent_error_detected(
+                        pattern.as_str(),
+                        pattern.class(),
+                    ));
+                }
+                None => {}
             }
         };
 
@@ -244,6 +755,16 @@ impl Agent {
     }
 }
 
+/// Wait for the next suspend/resume request.
+async fn suspension_changed(
+    suspension: &mut Option<watch::Receiver<bool>>,
+) -> std::result::Result<(), watch::error::RecvError> {
+    match suspension {
+        Some(suspended) => suspended.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Output captured from an agent run.
 #[derive(Debug, Clone)]
 pub struct AgentOutput {
@@ -306,6 +827,32 @@ impl AgentOutput {
         self.combined.contains(phrase)
     }
 
+    /// Check if any line of the combined output, trimmed, equals the phrase.
+    ///
+    /// Unlike `contains`, this ignores the phrase when it is quoted inside
+    /// other text, such as an echoed prompt.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wiggle_puppy_core::AgentOutput;
+    ///
+    /// let combined = "Prompt: output DONE when finished\n  DONE  ".to_string();
+    /// let output = AgentOutput {
+    ///     stdout: combined.clone(),
+    ///     stderr: String::new(),
+    ///     combined,
+    ///     exit_code: Some(0),
+    ///     duration_secs: 1.5,
+    /// };
+    ///
+    /// assert!(output.contains_line("DONE"));
+    /// assert!(!output.contains_line("finished"));
+    /// ```
+    pub fn contains_line(&self, phrase: &str) -> bool {
+        self.combined.lines().any(|line| line.trim() == phrase)
+    }
+
     /// Get the last N lines from the combined output.
     ///
     /// # Examples
@@ -348,14 +895,14 @@ impl AgentOutput {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::event::channel;
+    use crate::event::{channel, EventEnvelope};
 
     #[test]
     fn test_agent_new() {
         let agent = Agent::new(
             "claude",
             vec!["-p".to_string()],
-            vec!["FATAL ERROR".to_string()],
+            vec![ErrorPattern::literal("FATAL ERROR", ErrorClass::Fatal)],
             300,
         );
         assert_eq!(agent.command(), "claude");
@@ -476,7 +1023,7 @@ mod tests {
         // Drain events
         drop(tx);
         let mut events = Vec::new();
-        while let Some(event) = rx.recv().await {
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
             events.push(event);
         }
         assert!(!events.is_empty());
@@ -503,6 +1050,482 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prompt_delivery_from_str() {
+        assert_eq!("arg".parse(), Ok(PromptDelivery::Argument));
+        assert_eq!("stdin".parse(), Ok(PromptDelivery::Stdin));
+        assert_eq!("file".parse(), Ok(PromptDelivery::TempFile));
+        assert_eq!("placeholder".parse(), Ok(PromptDelivery::Placeholder));
+        assert!("carrier-pigeon".parse::<PromptDelivery>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_stdin_delivery() {
+        let agent = Agent::new("cat", vec![], vec![], 60).prompt_delivery(PromptDelivery::Stdin);
+        let (tx, _rx) = channel();
+
+        let output = agent.run("hello via stdin", &tx).await.unwrap();
+        assert_eq!(output.stdout, "hello via stdin");
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_temp_file_delivery() {
+        let agent = Agent::new("cat", vec![], vec![], 60).prompt_delivery(PromptDelivery::TempFile);
+        let (tx, _rx) = channel();
+
+        let output = agent.run("hello via file", &tx).await.unwrap();
+        assert_eq!(output.stdout, "hello via file");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_prompt_file_does_not_follow_planted_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let target = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_symlink_target_{}",
+            std::process::id()
+        ));
+        std::fs::write(&target, "precious").unwrap();
+        let next = PROMPT_FILE_COUNTER.load(Ordering::SeqCst);
+        let planted: Vec<PathBuf> = (next..next + 4)
+            .map(|n| {
+                std::env::temp_dir().join(format!(
+                    "wiggle-puppy-prompt-{}-{}.md",
+                    std::process::id(),
+                    n
+                ))
+            })
+            .collect();
+        for link in &planted {
+            std::os::unix::fs::symlink(&target, link).ok();
+        }
+
+        let file = PromptFile::create("secret prompt").await.unwrap();
+        assert!(!planted.contains(&file.path));
+        assert_eq!(
+            std::fs::read_to_string(&file.path).unwrap(),
+            "secret prompt"
+        );
+        let mode = std::fs::metadata(&file.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "precious");
+
+        drop(file);
+        for link in &planted {
+            std::fs::remove_file(link).ok();
+        }
+        std::fs::remove_file(&target).ok();
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_placeholder_delivery() {
+        let agent = Agent::new(
+            "sh",
+            vec![
+                "-c".to_string(),
+                "echo \"arg: $0\"; cat {prompt_file}".to_string(),
+                "{prompt}".to_string(),
+            ],
+            vec![],
+            60,
+        )
+        .prompt_delivery(PromptDelivery::Placeholder);
+        let (tx, _rx) = channel();
+
+        let output = agent.run("middle", &tx).await.unwrap();
+        assert_eq!(output.stdout, "arg: middle\nmiddle");
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_placeholder_delivery_requires_placeholder() {
+        let agent =
+            Agent::new("echo", vec![], vec![], 60).prompt_delivery(PromptDelivery::Placeholder);
+        let (tx, _rx) = channel();
+
+        let result = agent.run("test", &tx).await;
+        assert!(matches!(result, Err(Error::ConfigError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_oversized_prompt_falls_back_to_stdin() {
+        // With argument delivery, `cat` would treat the prompt as a file name.
+        let agent = Agent::new("cat", vec![], vec![], 60);
+        let (tx, mut rx) = channel();
+
+        let prompt = "x".repeat(MAX_ARG_PROMPT_BYTES + 1);
+        let output = agent.run(&prompt, &tx).await.unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout.len(), prompt.len());
+
+        drop(tx);
+        let mut warned = false;
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::Warning { message } = event {
+                warned |= message.contains("stdin");
+            }
+        }
+        assert!(warned, "expected a warning about the stdin fallback");
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_timeout_while_streaming() {
+        // The backgrounded sleep keeps stdout open, so the streams never reach EOF.
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 1);
+        let (tx, mut rx) = channel();
+
+        let started = Instant::now();
+        let result = agent.run("echo started; sleep 30 & wait", &tx).await;
+        assert!(matches!(
+            result,
+            Err(Error::AgentTimeout { timeout_secs: 1 })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        drop(tx);
+        let mut timed_out = false;
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            timed_out |= matches!(event, Event::AgentTimeout { timeout_secs: 1 });
+        }
+        assert!(timed_out, "expected an AgentTimeout event");
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_idle_timeout() {
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 60).idle_timeout_secs(1);
+        let (tx, mut rx) = channel();
+
+        let result = agent.run("echo started; sleep 30", &tx).await;
+        assert!(matches!(
+            result,
+            Err(Error::AgentIdleTimeout { idle_secs: 1 })
+        ));
+
+        drop(tx);
+        let mut idle = false;
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            idle |= matches!(event, Event::AgentIdleTimeout { idle_secs: 1 });
+        }
+        assert!(idle, "expected an AgentIdleTimeout event");
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_idle_timeout_reset_by_output() {
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 60).idle_timeout_secs(1);
+        let (tx, _rx) = channel();
+
+        let output = agent
+            .run("for i in 1 2 3 4; do echo $i; sleep 0.4; done", &tx)
+            .await
+            .unwrap();
+        assert_eq!(output.line_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_aborts_on_error_pattern() {
+        let agent = Agent::new(
+            "sh",
+            vec!["-c".to_string()],
+            vec![ErrorPattern::literal("FATAL ERROR", ErrorClass::Fatal)],
+            60,
+        );
+        let (tx, mut rx) = channel();
+
+        let started = Instant::now();
+        let result = agent
+            // Both lines go to stderr so their order (and line numbers) is fixed
+            .run(
+                "echo working >&2; echo 'FATAL ERROR: boom' >&2; sleep 30",
+                &tx,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::AgentErrorDetected { ref pattern, class: ErrorClass::Fatal })
+                if pattern == "FATAL ERROR"
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        drop(tx);
+        let mut detected = None;
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::AgentErrorDetected {
+                line, line_number, ..
+            } = event
+            {
+                detected = Some((line, line_number));
+            }
+        }
+        assert_eq!(detected, Some(("FATAL ERROR: boom".to_string(), 2)));
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_ignore_pattern_takes_precedence() {
+        let agent = Agent::new(
+            "sh",
+            vec!["-c".to_string()],
+            vec![
+                ErrorPattern::new(r"^warning: .*error", ErrorClass::Ignore).unwrap(),
+                ErrorPattern::new(r"error", ErrorClass::Transient).unwrap(),
+            ],
+            60,
+        );
+        let (tx, _rx) = channel();
+
+        let output = agent
+            .run("echo 'warning: deprecated error handler'", &tx)
+            .await
+            .unwrap();
+        assert!(output.success());
+
+        let result = agent.run("echo 'error: real failure'", &tx).await;
+        assert!(matches!(
+            result,
+            Err(Error::AgentErrorDetected {
+                class: ErrorClass::Transient,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_user_ignore_pattern_overrides_default() {
+        // Added after the defaults, as --error-pattern does
+        let config = crate::config::Config::new().add_error_pattern(ErrorPattern::literal(
+            "Invalid API key in fixture",
+            ErrorClass::Ignore,
+        ));
+        let agent = Agent::new("echo", vec![], config.error_patterns, 60);
+        let (tx, _rx) = channel();
+
+        let output = agent
+            .run("test output: Invalid API key in fixture", &tx)
+            .await
+            .unwrap();
+        assert!(output.success());
+
+        let result = agent.run("Invalid API key", &tx).await;
+        assert!(matches!(
+            result,
+            Err(Error::AgentErrorDetected {
+                class: ErrorClass::Fatal,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_usage_limit_epoch() {
+        let now = Local::now();
+        let limit = parse_usage_limit("Claude AI usage limit reached|1749924000", now).unwrap();
+        assert_eq!(
+            limit.resets_at,
+            Some(Utc.timestamp_opt(1749924000, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_usage_limit_time_of_day() {
+        let now = Local.with_ymd_and_hms(2025, 6, 1, 10, 0, 0).unwrap();
+
+        let limit = parse_usage_limit("5-hour limit reached ∙ resets 3pm", now).unwrap();
+        let expected = Local.with_ymd_and_hms(2025, 6, 1, 15, 0, 0).unwrap();
+        assert_eq!(limit.resets_at, Some(expected.with_timezone(&Utc)));
+
+        // A time already passed today means tomorrow
+        let limit = parse_usage_limit(
+            "Claude usage limit reached. Your limit will reset at 9:30am (Europe/London).",
+            now,
+        )
+        .unwrap();
+        let expected = Local.with_ymd_and_hms(2025, 6, 2, 9, 30, 0).unwrap();
+        assert_eq!(limit.resets_at, Some(expected.with_timezone(&Utc)));
+    }
+
+    #[test]
+    fn test_parse_usage_limit_without_reset_time() {
+        let limit = parse_usage_limit("You've hit your limit", Local::now()).unwrap();
+        assert_eq!(limit.resets_at, None);
+    }
+
+    #[test]
+    fn test_parse_usage_limit_ignores_other_output() {
+        assert!(parse_usage_limit("Implemented the limit reached check", Local::now()).is_none());
+        assert!(parse_usage_limit("all good", Local::now()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_usage_limit() {
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 60);
+        let (tx, mut rx) = channel();
+
+        let reset = Utc::now().timestamp() + 3600;
+        let result = agent
+            .run(
+                &format!("echo 'Claude AI usage limit reached|{}'; sleep 30", reset),
+                &tx,
+            )
+            .await;
+        let expected = Utc
+            .timestamp_opt(reset + USAGE_LIMIT_GRACE_SECS, 0)
+            .unwrap();
+        assert!(matches!(
+            result,
+            Err(Error::UsageLimitReached { resume_at }) if resume_at == expected
+        ));
+
+        drop(tx);
+        let mut rate_limited = None;
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::RateLimited { resume_at } = event {
+                rate_limited = Some(resume_at);
+            }
+        }
+        assert_eq!(rate_limited, Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_usage_limit_fallback_wait() {
+        let agent = Agent::new("echo", vec![], vec![], 60).usage_limit_wait_secs(600);
+        let (tx, _rx) = channel();
+
+        let before = Utc::now();
+        let result = agent.run("You've hit your limit", &tx).await;
+        match result {
+            Err(Error::UsageLimitReached { resume_at }) => {
+                assert!(resume_at >= before + chrono::Duration::seconds(600));
+                assert!(resume_at <= Utc::now() + chrono::Duration::seconds(600));
+            }
+            other => panic!("expected UsageLimitReached, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_usage_limit_past_reset_uses_fallback_wait() {
+        let agent = Agent::new("echo", vec![], vec![], 60).usage_limit_wait_secs(600);
+        let (tx, _rx) = channel();
+
+        let before = Utc::now();
+        let result = agent
+            .run("Claude AI usage limit reached|1000000000", &tx)
+            .await;
+        match result {
+            Err(Error::UsageLimitReached { resume_at }) => {
+                assert!(resume_at >= before + chrono::Duration::seconds(600));
+            }
+            other => panic!("expected UsageLimitReached, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_ignore_pattern_suppresses_usage_limit() {
+        let agent = Agent::new(
+            "echo",
+            vec![],
+            vec![ErrorPattern::literal("fixtures/", ErrorClass::Ignore)],
+            60,
+        );
+        let (tx, _rx) = channel();
+
+        let output = agent
+            .run(
+                "fixtures/limit.txt: Claude AI usage limit reached|1000000000",
+                &tx,
+            )
+            .await
+            .unwrap();
+        assert!(output.success());
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_cancelled_sends_sigterm() {
+        let token = CancellationToken::new();
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 60)
+            .cancellation(token.clone())
+            .kill_grace_secs(10);
+        let (tx, _rx) = channel();
+
+        let canceller = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            token.cancel();
+        });
+
+        let started = Instant::now();
+        let result = agent
+            .run("trap 'exit 0' TERM; while true; do sleep 0.1; done", &tx)
+            .await;
+        canceller.await.unwrap();
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        // The trap exits well inside the grace period, so no SIGKILL wait
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_cancelled_escalates_to_sigkill() {
+        let token = CancellationToken::new();
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 60)
+            .cancellation(token.clone())
+            .kill_grace_secs(1);
+        let (tx, _rx) = channel();
+
+        let canceller = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            token.cancel();
+            Instant::now()
+        });
+
+        let result = agent
+            .run("trap '' TERM; while true; do sleep 0.1; done", &tx)
+            .await;
+        let cancelled_at = canceller.await.unwrap();
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        // SIGTERM is ignored, so the run only ends once the grace period lapses
+        assert!(cancelled_at.elapsed() >= Duration::from_secs(1));
+        assert!(cancelled_at.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_agent_run_suspended_pauses_agent_and_timeout() {
+        let (suspend_tx, suspend_rx) = watch::channel(false);
+        // The run timeout is shorter than the suspension, so it only
+        // succeeds if the clock stops while the agent is suspended
+        let agent = Agent::new("sh", vec!["-c".to_string()], vec![], 1).suspension(suspend_rx);
+        let (tx, mut rx) = channel();
+
+        let controller = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            suspend_tx.send_replace(true);
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            suspend_tx.send_replace(false);
+            suspend_tx
+        });
+
+        let start = Instant::now();
+        let result = agent.run("sleep 0.5; echo done", &tx).await;
+        let _suspend_tx = controller.await.unwrap();
+
+        let output = result.expect("suspended time should not count towards the timeout");
+        assert!(output.stdout.contains("done"));
+        // The agent was stopped, so it can't have finished before the resume
+        assert!(start.elapsed() >= Duration::from_millis(1600));
+
+        drop(tx);
+        let mut paused = false;
+        let mut resumed = false;
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            match event {
+                Event::Paused { agent_suspended } => paused = agent_suspended,
+                Event::Resumed { paused_secs } => resumed = paused_secs >= 1.0,
+                _ => {}
+            }
+        }
+        assert!(paused, "should have emitted Paused with agent_suspended");
+        assert!(resumed, "should have emitted Resumed with the pause length");
+    }
+
     #[tokio::test]
     async fn test_agent_run_with_stderr() {
         // Use sh to echo to stderr
@@ -524,7 +1547,7 @@ mod tests {
         drop(tx);
         let mut stdout_events = 0;
         let mut stderr_events = 0;
-        while let Some(event) = rx.recv().await {
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
             if let Event::AgentOutput { is_stderr, .. } = event {
                 if is_stderr {
                     stderr_events += 1;
@@ -0,0 +1,263 @@
+//! Crash-safe run checkpoints.
+//!
+//! This module provides `Checkpoint`, a small JSON state file the runner
+//! rewrites after every iteration. If wiggle-puppy dies mid-run, a restart
+//! with resume enabled continues the iteration count and failure counters
+//! from the checkpoint instead of starting over.
+
+use crate::config::{Config, PromptSource};
+use crate::error::{Error, Result};
+use crate::runner::Outcome;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Current checkpoint file format version.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// FNV-1a 64-bit offset basis.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1a 64-bit prime.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Just the format version of a checkpoint, read before the rest.
+#[derive(Deserialize)]
+struct CheckpointVersion {
+    version: u32,
+}
+
+/// Persistent state of a run, saved after each iteration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    /// Checkpoint file format version.
+    pub version: u32,
+
+    /// Identifier of the run, kept across resumes and stamped on its events.
+    pub run_id: Uuid,
+
+    /// Number of iterations completed so far.
+    pub iteration: u32,
+
+    /// Consecutive agent failures at the end of the last iteration.
+    pub consecutive_failures: u32,
+
+    /// Hash of the settings that identify the run (see `config_hash`).
+    pub config_hash: String,
+
+    /// When the run first started.
+    pub started_at: DateTime<Utc>,
+
+    /// When the checkpoint was last written.
+    pub updated_at: DateTime<Utc>,
+
+    /// How the run finished, once its work is over; such a run is not resumed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished: Option<Outcome>,
+}
+
+impl Checkpoint {
+    /// Create a checkpoint for a fresh run of the given configuration.
+    pub fn new(config: &Config, run_id: Uuid) -> Self {
+        let now = Utc::now();
+        Self {
+            version: CHECKPOINT_VERSION,
+            run_id,
+            iteration: 0,
+            consecutive_failures: 0,
+            config_hash: config_hash(config),
+            started_at: now,
+            updated_at: now,
+            finished: None,
+        }
+    }
+
+    /// Load a checkpoint from a JSON file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed, or
+    /// `Error::CheckpointVersionMismatch` if it is in another format version.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            std::fs::read_to_string(path).map_err(|source| Error::CheckpointReadError {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        let parse_error = |source| Error::CheckpointParseError {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        // Check the version first, since another format may not parse at all
+        let CheckpointVersion { version } = serde_json::from_str(&content).map_err(parse_error)?;
+        if version != CHECKPOINT_VERSION {
+            return Err(Error::CheckpointVersionMismatch {
+                path: path.to_path_buf(),
+                expected: CHECKPOINT_VERSION,
+                found: version,
+            });
+        }
+        serde_json::from_str(&content).map_err(parse_error)
+    }
+
+    /// Save the checkpoint to a JSON file.
+    ///
+    /// The file is written next to its destination and renamed into place,
+    /// so a crash mid-write never leaves a truncated checkpoint behind.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content =
+            serde_json::to_string_pretty(self).map_err(|source| Error::CheckpointWriteError {
+                path: path.to_path_buf(),
+                source: source.into(),
+            })?;
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, content)
+            .and_then(|()| std::fs::rename(&tmp_path, path))
+            .map_err(|source| Error::CheckpointWriteError {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    /// Record the state at the end of an iteration.
+    pub fn update(&mut self, iteration: u32, consecutive_failures: u32) {
+        self.iteration = iteration;
+        self.consecutive_failures = consecutive_failures;
+        self.updated_at = Utc::now();
+    }
+}
+
+/// Hash the settings that identify a run.
+///
+/// Covers the agent command and arguments, prompt delivery, the prompt
+/// source, the PRD path and the completion phrase. Tuning knobs such as
+/// `max_iterations`, delays, timeouts and retry settings are left out so a
+/// resumed run may change them.
+pub fn config_hash(config: &Config) -> String {
+    let fields = [
+        config.agent_command.clone(),
+        config.agent_args.join("\u{1f}"),
+        config.prompt_delivery.to_string(),
+        config
+            .prompt_sources
+            .iter()
+            .map(|source| match source {
+                PromptSource::File(path) => format!("file:{}", path.display()),
+                PromptSource::Text(text) => format!("text:{}", text),
+            })
+            .collect::<Vec<_>>()
+            .join("\u{1f}"),
+        display_path(config.prd_path.as_deref()),
+        config.completion_phrase.clone(),
+    ];
+    format!("{:016x}", fnv1a(fields.join("\0").as_bytes()))
+}
+
+/// Format an optional path for hashing.
+fn display_path(path: Option<&Path>) -> String {
+    path.map(|p| p.display().to_string()).unwrap_or_default()
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_checkpoint() {
+        let config = Config::new().prompt_text("test");
+        let run_id = Uuid::new_v4();
+        let checkpoint = Checkpoint::new(&config, run_id);
+
+        assert_eq!(checkpoint.version, CHECKPOINT_VERSION);
+        assert_eq!(checkpoint.iteration, 0);
+        assert_eq!(checkpoint.consecutive_failures, 0);
+        assert_eq!(checkpoint.config_hash, config_hash(&config));
+        assert_eq!(checkpoint.run_id, run_id);
+    }
+
+    #[test]
+    fn test_config_hash_ignores_tuning_knobs() {
+        let config = Config::new().prompt_text("test");
+        let tuned = config.clone().max_iterations(100).max_retries(7);
+        assert_eq!(config_hash(&config), config_hash(&tuned));
+
+        let other_prompt = config.clone().prompt_text("other");
+        assert_ne!(config_hash(&config), config_hash(&other_prompt));
+
+        let other_agent = config.clone().agent_args(vec!["--print".to_string()]);
+        assert_ne!(config_hash(&config), config_hash(&other_agent));
+    }
+
+    #[test]
+    fn test_fnv1a_known_values() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_checkpoint_{}.json",
+            std::process::id()
+        ));
+        let mut checkpoint = Checkpoint::new(&Config::new().prompt_text("test"), Uuid::new_v4());
+        checkpoint.update(14, 2);
+
+        checkpoint.save(&path).expect("should save");
+        let loaded = Checkpoint::load(&path).expect("should load");
+        assert_eq!(loaded, checkpoint);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_load_rejects_other_version() {
+        let path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_checkpoint_version_{}.json",
+            std::process::id()
+        ));
+        let mut checkpoint = Checkpoint::new(&Config::new().prompt_text("test"), Uuid::new_v4());
+        checkpoint.version = CHECKPOINT_VERSION + 1;
+        checkpoint.save(&path).expect("should save");
+
+        let result = Checkpoint::load(&path);
+        assert!(matches!(
+            result,
+            Err(Error::CheckpointVersionMismatch { expected, found, .. })
+                if expected == CHECKPOINT_VERSION && found == CHECKPOINT_VERSION + 1
+        ));
+
+        // A future format with fields this version doesn't know still reports the version
+        std::fs::write(&path, r#"{"version": 99, "runs": []}"#).unwrap();
+        assert!(matches!(
+            Checkpoint::load(&path),
+            Err(Error::CheckpointVersionMismatch { found: 99, .. })
+        ));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let result = Checkpoint::load("/nonexistent/wiggle-puppy/checkpoint.json");
+        assert!(matches!(result, Err(Error::CheckpointReadError { .. })));
+    }
+}
@@ -0,0 +1,695 @@
+//! Pluggable completion detection.
+//!
+//! This module provides the `CompletionDetector` trait, which decides from an
+//! iteration's agent output whether the agent has signalled that it is done,
+//! and built-in detectors for the common cases. Detectors configured on
+//! `Config` replace the default completion phrase check; library users can
+//! supply their own by implementing the trait. `CompletionPolicy` decides
+//! how the agent's signal combines with the PRD.
+
+use crate::agent::AgentOutput;
+use crate::error::{Error, Result};
+use crate::event::{CompletionReason, StopReason};
+use regex::Regex;
+use uuid::Uuid;
+
+/// Which signals complete the run.
+///
+/// The agent's signal is the completion phrase or a matching completion
+/// detector; the PRD signal is every story passing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompletionPolicy {
+    /// Either signal completes the run.
+    #[default]
+    Any,
+    /// The agent must signal completion and, if a PRD is configured, every
+    /// story must pass.
+    All,
+    /// Only every PRD story passing completes the run; requires a PRD.
+    PrdOnly,
+    /// Only the agent's signal completes the run; the PRD is ignored.
+    PhraseOnly,
+}
+
+impl CompletionPolicy {
+    /// Combine this iteration's signals into a completion reason, if any.
+    ///
+    /// `prd_complete` is `None` when no PRD is configured.
+    pub(crate) fn resolve(
+        self,
+        signal: Option<CompletionReason>,
+        prd_complete: Option<bool>,
+    ) -> Option<CompletionReason> {
+        match (self, signal, prd_complete) {
+            (CompletionPolicy::PhraseOnly, signal, _) => signal,
+            (CompletionPolicy::PrdOnly, _, Some(true)) => {
+                Some(CompletionReason::AllStoriesComplete)
+            }
+            (CompletionPolicy::PrdOnly, _, _) => None,
+            (_, Some(_), Some(true)) => Some(CompletionReason::Both),
+            (CompletionPolicy::Any, None, Some(true)) => Some(CompletionReason::AllStoriesComplete),
+            (CompletionPolicy::Any, signal, _) => signal,
+            (CompletionPolicy::All, signal, None) => signal,
+            (CompletionPolicy::All, _, Some(_)) => None,
+        }
+    }
+
+    /// Whether a complete PRD alone is enough to finish the run.
+    pub(crate) fn accepts_prd_alone(self) -> bool {
+        matches!(self, CompletionPolicy::Any | CompletionPolicy::PrdOnly)
+    }
+}
+
+impl std::fmt::Display for CompletionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompletionPolicy::Any => write!(f, "any"),
+            CompletionPolicy::All => write!(f, "all"),
+            CompletionPolicy::PrdOnly => write!(f, "prd-only"),
+            CompletionPolicy::PhraseOnly => write!(f, "phrase-only"),
+        }
+    }
+}
+
+impl std::str::FromStr for CompletionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(CompletionPolicy::Any),
+            "all" => Ok(CompletionPolicy::All),
+            "prd-only" | "prd" => Ok(CompletionPolicy::PrdOnly),
+            "phrase-only" | "phrase" => Ok(CompletionPolicy::PhraseOnly),
+            other => Err(format!(
+                "unknown completion policy '{}' (expected any, all, prd-only or phrase-only)",
+                other
+            )),
+        }
+    }
+}
+
+/// Decides whether an iteration's output signals completion.
+///
+/// `detect` is called once per iteration, in order, so detectors may keep
+/// state across iterations. Any `Clone` type implementing this trait gets
+/// `clone_box` for free.
+///
+/// # Examples
+///
+/// ```
+/// use wiggle_puppy_core::{AgentOutput, CompletionDetector};
+///
+/// /// Completes once the agent reports zero failing tests.
+/// #[derive(Debug, Clone)]
+/// struct NoFailures;
+///
+/// impl CompletionDetector for NoFailures {
+///     fn name(&self) -> String {
+///         "no failing tests".to_string()
+///     }
+///
+///     fn detect(&mut self, output: &AgentOutput) -> bool {
+///         output.contains("0 failed")
+///     }
+/// }
+/// ```
+pub trait CompletionDetector: DetectorClone + std::fmt::Debug + Send + Sync {
+    /// Describe the detector, for `CompletionReason::DetectorMatched`.
+    fn name(&self) -> String;
+
+    /// Check an iteration's output.
+    fn detect(&mut self, output: &AgentOutput) -> bool;
+}
+
+/// Cloning support for boxed detectors, implemented for every `Clone` detector.
+pub trait DetectorClone {
+    /// Clone the detector into a new box.
+    fn clone_box(&self) -> Box<dyn CompletionDetector>;
+}
+
+impl<T> DetectorClone for T
+where
+    T: CompletionDetector + Clone + 'static,
+{
+    fn clone_box(&self) -> Box<dyn CompletionDetector> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn CompletionDetector> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Completes when the output contains a phrase anywhere.
+#[derive(Debug, Clone)]
+pub struct SubstringDetector {
+    /// The phrase to look for.
+    phrase: String,
+}
+
+impl SubstringDetector {
+    /// Create a detector for the given phrase.
+    pub fn new(phrase: impl Into<String>) -> Self {
+        Self {
+            phrase: phrase.into(),
+        }
+    }
+}
+
+impl CompletionDetector for SubstringDetector {
+    fn name(&self) -> String {
+        format!("substring \"{}\"", self.phrase)
+    }
+
+    fn detect(&mut self, output: &AgentOutput) -> bool {
+        output.contains(&self.phrase)
+    }
+}
+
+/// Completes when any output line matches a regular expression.
+#[derive(Debug, Clone)]
+pub struct RegexDetector {
+    /// The compiled regular expression.
+    regex: Regex,
+}
+
+impl RegexDetector {
+    /// Create a detector from a regular expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidCompletionPattern` if the regex does not compile.
+    pub fn new(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern).map_err(|source| Error::InvalidCompletionPattern {
+            pattern: pattern.to_string(),
+            source,
+        })?;
+        Ok(Self { regex })
+    }
+}
+
+impl CompletionDetector for RegexDetector {
+    fn name(&self) -> String {
+        format!("regex `{}`", self.regex.as_str())
+    }
+
+    fn detect(&mut self, output: &AgentOutput) -> bool {
+        output
+            .combined
+            .lines()
+            .any(|line| self.regex.is_match(line))
+    }
+}
+
+/// Completes when a sentinel appears on a line of its own.
+///
+/// Surrounding whitespace is ignored, so a sentinel quoted inside a sentence
+/// (as when the agent echoes its instructions) does not count.
+#[derive(Debug, Clone)]
+pub struct SentinelLineDetector {
+    /// The sentinel text.
+    sentinel: String,
+}
+
+impl SentinelLineDetector {
+    /// Create a detector for the given sentinel.
+    pub fn new(sentinel: impl Into<String>) -> Self {
+        Self {
+            sentinel: sentinel.into(),
+        }
+    }
+}
+
+impl CompletionDetector for SentinelLineDetector {
+    fn name(&self) -> String {
+        format!("sentinel line \"{}\"", self.sentinel)
+    }
+
+    fn detect(&mut self, output: &AgentOutput) -> bool {
+        output.contains_line(&self.sentinel)
+    }
+}
+
+/// Completes when a phrase appears within the last N lines of output.
+#[derive(Debug, Clone)]
+pub struct LastLinesDetector {
+    /// The phrase to look for.
+    phrase: String,
+    /// How many trailing lines to search.
+    lines: usize,
+}
+
+impl LastLinesDetector {
+    /// Create a detector for a phrase in the last `lines` lines.
+    pub fn new(phrase: impl Into<String>, lines: usize) -> Self {
+        Self {
+            phrase: phrase.into(),
+            lines,
+        }
+    }
+}
+
+impl CompletionDetector for LastLinesDetector {
+    fn name(&self) -> String {
+        format!("\"{}\" in the last {} lines", self.phrase, self.lines)
+    }
+
+    fn detect(&mut self, output: &AgentOutput) -> bool {
+        output
+            .last_lines(self.lines)
+            .iter()
+            .any(|line| line.contains(&self.phrase))
+    }
+}
+
+/// Completes when a line is a JSON object with a given field value,
+/// such as `{"status": "complete"}`.
+#[derive(Debug, Clone)]
+pub struct JsonSentinelDetector {
+    /// The field to check.
+    key: String,
+    /// The expected value.
+    value: serde_json::Value,
+}
+
+impl JsonSentinelDetector {
+    /// Create a detector for `key` equal to `value`.
+    pub fn new(key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl CompletionDetector for JsonSentinelDetector {
+    fn name(&self) -> String {
+        format!("JSON {{\"{}\": {}}}", self.key, self.value)
+    }
+
+    fn detect(&mut self, output: &AgentOutput) -> bool {
+        output.combined.lines().any(|line| {
+            let line = line.trim();
+            line.starts_with('{')
+                && serde_json::from_str::<serde_json::Value>(line)
+                    .is_ok_and(|object| object.get(&self.key) == Some(&self.value))
+        })
+    }
+}
+
+/// Completes once another detector has matched N iterations in a row.
+#[derive(Debug, Clone)]
+pub struct ConsecutiveDetector {
+    /// The wrapped detector.
+    inner: Box<dyn CompletionDetector>,
+    /// Matches required in a row.
+    required: u32,
+    /// Matches so far in the current streak.
+    streak: u32,
+}
+
+impl ConsecutiveDetector {
+    /// Require `inner` to match in `required` consecutive iterations.
+    pub fn new(inner: impl CompletionDetector + 'static, required: u32) -> Self {
+        Self::boxed(Box::new(inner), required)
+    }
+
+    /// Like `new`, for an already boxed detector.
+    pub fn boxed(inner: Box<dyn CompletionDetector>, required: u32) -> Self {
+        Self {
+            inner,
+            required,
+            streak: 0,
+        }
+    }
+}
+
+impl CompletionDetector for ConsecutiveDetector {
+    fn name(&self) -> String {
+        format!(
+            "{} for {} consecutive iterations",
+            self.inner.name(),
+            self.required
+        )
+    }
+
+    fn detect(&mut self, output: &AgentOutput) -> bool {
+        if self.inner.detect(output) {
+            self.streak += 1;
+        } else {
+            self.streak = 0;
+        }
+        self.streak >= self.required
+    }
+}
+
+/// Parse a detector spec, as accepted by the CLI's `--completion-detector`.
+///
+/// Supported forms:
+///
+/// - `substring:TEXT`
+/// - `regex:REGEX`
+/// - `line:TEXT` (sentinel on its own line)
+/// - `last-lines:N:TEXT`
+/// - `json:KEY=VALUE` (`VALUE` is parsed as JSON, falling back to a string)
+/// - `consecutive:N:SPEC`
+///
+/// # Examples
+///
+/// ```
+/// use wiggle_puppy_core::completion::parse_detector;
+///
+/// let detector = parse_detector("consecutive:2:line:DONE").unwrap();
+/// assert_eq!(detector.name(), "sentinel line \"DONE\" for 2 consecutive iterations");
+/// ```
+pub fn parse_detector(spec: &str) -> std::result::Result<Box<dyn CompletionDetector>, String> {
+    let (kind, rest) = spec.split_once(':').ok_or_else(|| {
+        format!(
+            "invalid completion detector '{}' (expected KIND:ARGS)",
+            spec
+        )
+    })?;
+    let count = |rest: &str| -> std::result::Result<(u32, String), String> {
+        let (n, tail) = rest.split_once(':').ok_or_else(|| {
+            format!(
+                "invalid completion detector '{}' (expected {}:N:...)",
+                spec, kind
+            )
+        })?;
+        let n = n
+            .parse()
+            .map_err(|_| format!("invalid count '{}' in completion detector '{}'", n, spec))?;
+        Ok((n, tail.to_string()))
+    };
+
+    match kind {
+        "substring" => Ok(Box::new(SubstringDetector::new(rest))),
+        "regex" => RegexDetector::new(rest)
+            .map(|d| Box::new(d) as Box<dyn CompletionDetector>)
+            .map_err(|e| e.to_string()),
+        "line" => Ok(Box::new(SentinelLineDetector::new(rest))),
+        "last-lines" => {
+            let (lines, phrase) = count(rest)?;
+            Ok(Box::new(LastLinesDetector::new(phrase, lines as usize)))
+        }
+        "json" => {
+            let (key, value) = rest.split_once('=').ok_or_else(|| {
+                format!(
+                    "invalid completion detector '{}' (expected json:KEY=VALUE)",
+                    spec
+                )
+            })?;
+            let value = serde_json::from_str(value)
+                .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+            Ok(Box::new(JsonSentinelDetector::new(key, value)))
+        }
+        "consecutive" => {
+            let (required, inner) = count(rest)?;
+            Ok(Box::new(ConsecutiveDetector::boxed(
+                parse_detector(&inner)?,
+                required,
+            )))
+        }
+        other => Err(format!(
+            "unknown completion detector '{}' (expected substring, regex, line, last-lines, json or consecutive)",
+            other
+        )),
+    }
+}
+
+/// Run every detector on the output and return the name of the first match.
+///
+/// All detectors see every iteration, so stateful ones stay in step.
+pub(crate) fn detect(
+    detectors: &mut [Box<dyn CompletionDetector>],
+    output: &AgentOutput,
+) -> Option<String> {
+    let mut matched = None;
+    for detector in detectors.iter_mut() {
+        if detector.detect(output) && matched.is_none() {
+            matched = Some(detector.name());
+        }
+    }
+    matched
+}
+
+/// Prefix inside `<promise>` tags of the sentinel for a blocked agent.
+const BLOCKED_PREFIX: &str = "BLOCKED:";
+
+/// Prefix inside `<promise>` tags of the sentinel for an agent asking a question.
+const NEEDS_INPUT_PREFIX: &str = "NEEDS_INPUT:";
+
+/// Find a `<promise>BLOCKED: reason</promise>` or
+/// `<promise>NEEDS_INPUT: question</promise>` sentinel on a line by itself.
+///
+/// Returns the stop reason for the first one found.
+pub(crate) fn agent_stop_signal(output: &AgentOutput) -> Option<StopReason> {
+    output.combined.lines().find_map(|line| {
+        let body = line
+            .trim()
+            .strip_prefix("<promise>")?
+            .strip_suffix("</promise>")?;
+        if let Some(reason) = body.strip_prefix(BLOCKED_PREFIX) {
+            Some(StopReason::AgentBlocked {
+                reason: reason.trim().to_string(),
+            })
+        } else {
+            body.strip_prefix(NEEDS_INPUT_PREFIX)
+                .map(|question| StopReason::AwaitingInput {
+                    question: question.trim().to_string(),
+                })
+        }
+    })
+}
+
+/// Number of trailing run id characters used as the completion phrase nonce.
+const NONCE_LEN: usize = 4;
+
+/// Add a nonce derived from the run id to a completion phrase.
+///
+/// The nonce goes before a trailing closing tag, so
+/// `<promise>COMPLETE</promise>` becomes `<promise>COMPLETE-3f9a</promise>`;
+/// other phrases get it appended.
+pub(crate) fn phrase_with_nonce(phrase: &str, run_id: Uuid) -> String {
+    let run_id = run_id.simple().to_string();
+    let nonce = &run_id[run_id.len().saturating_sub(NONCE_LEN)..];
+    match phrase.rfind("</") {
+        Some(index) if phrase.ends_with('>') => {
+            format!("{}-{}{}", &phrase[..index], nonce, &phrase[index..])
+        }
+        _ => format!("{}-{}", phrase, nonce),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(text: &str) -> AgentOutput {
+        AgentOutput {
+            stdout: text.to_string(),
+            combined: text.to_string(),
+            ..AgentOutput::empty()
+        }
+    }
+
+    #[test]
+    fn test_substring_detector() {
+        let mut detector = SubstringDetector::new("DONE");
+        assert!(detector.detect(&output("all DONE here")));
+        assert!(!detector.detect(&output("not yet")));
+    }
+
+    #[test]
+    fn test_regex_detector() {
+        let mut detector = RegexDetector::new(r"^All \d+ tasks complete$").unwrap();
+        assert!(detector.detect(&output("working\nAll 5 tasks complete")));
+        assert!(!detector.detect(&output("All tasks complete")));
+        assert!(matches!(
+            RegexDetector::new("(unclosed"),
+            Err(Error::InvalidCompletionPattern { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sentinel_line_detector() {
+        let mut detector = SentinelLineDetector::new("<promise>COMPLETE</promise>");
+        assert!(detector.detect(&output("done\n  <promise>COMPLETE</promise>  \n")));
+        assert!(!detector.detect(&output(
+            "When finished, output exactly: <promise>COMPLETE</promise>"
+        )));
+    }
+
+    #[test]
+    fn test_last_lines_detector() {
+        let mut detector = LastLinesDetector::new("DONE", 2);
+        assert!(detector.detect(&output("a\nb\nDONE\nc")));
+        assert!(!detector.detect(&output("DONE\na\nb\nc")));
+    }
+
+    #[test]
+    fn test_json_sentinel_detector() {
+        let mut detector = JsonSentinelDetector::new("status", "complete");
+        assert!(detector.detect(&output("log\n{\"status\": \"complete\", \"tasks\": 3}")));
+        assert!(!detector.detect(&output("{\"status\": \"working\"}")));
+        assert!(!detector.detect(&output("status: complete")));
+    }
+
+    #[test]
+    fn test_consecutive_detector() {
+        let mut detector = ConsecutiveDetector::new(SubstringDetector::new("DONE"), 2);
+        assert!(!detector.detect(&output("DONE")));
+        assert!(!detector.detect(&output("oops")));
+        assert!(!detector.detect(&output("DONE")));
+        assert!(detector.detect(&output("DONE")));
+    }
+
+    #[test]
+    fn test_cloned_detector_keeps_state_separate() {
+        let mut original: Box<dyn CompletionDetector> =
+            Box::new(ConsecutiveDetector::new(SubstringDetector::new("DONE"), 2));
+        let mut copy = original.clone();
+        assert!(!original.detect(&output("DONE")));
+        assert!(original.detect(&output("DONE")));
+        assert!(!copy.detect(&output("DONE")));
+    }
+
+    #[test]
+    fn test_parse_detector() {
+        let cases = [
+            ("substring:DONE", "substring \"DONE\""),
+            ("regex:^ok$", "regex `^ok$`"),
+            ("line:DONE", "sentinel line \"DONE\""),
+            ("last-lines:5:DONE", "\"DONE\" in the last 5 lines"),
+            ("json:done=true", "JSON {\"done\": true}"),
+            ("json:status=complete", "JSON {\"status\": \"complete\"}"),
+            (
+                "consecutive:3:substring:a:b",
+                "substring \"a:b\" for 3 consecutive iterations",
+            ),
+        ];
+        for (spec, name) in cases {
+            assert_eq!(parse_detector(spec).unwrap().name(), name, "{}", spec);
+        }
+
+        assert!(parse_detector("DONE").is_err());
+        assert!(parse_detector("fuzzy:DONE").is_err());
+        assert!(parse_detector("last-lines:x:DONE").is_err());
+        assert!(parse_detector("regex:(").is_err());
+    }
+
+    #[test]
+    fn test_detect_runs_every_detector() {
+        let mut detectors: Vec<Box<dyn CompletionDetector>> = vec![
+            Box::new(SubstringDetector::new("DONE")),
+            Box::new(ConsecutiveDetector::new(SubstringDetector::new("DONE"), 2)),
+        ];
+        assert_eq!(
+            detect(&mut detectors, &output("DONE")),
+            Some("substring \"DONE\"".to_string())
+        );
+        // The consecutive detector saw the first iteration too
+        detectors.remove(0);
+        assert_eq!(
+            detect(&mut detectors, &output("DONE")),
+            Some("substring \"DONE\" for 2 consecutive iterations".to_string())
+        );
+    }
+
+    #[test]
+    fn test_completion_policy_resolve() {
+        use CompletionPolicy::*;
+        let phrase = || Some(CompletionReason::CompletionPhraseDetected);
+
+        // No PRD configured
+        for policy in [Any, All, PhraseOnly] {
+            assert_eq!(policy.resolve(phrase(), None), phrase());
+            assert_eq!(policy.resolve(None, None), None);
+        }
+        assert_eq!(PrdOnly.resolve(phrase(), None), None);
+
+        // Premature phrase with an incomplete PRD
+        assert_eq!(Any.resolve(phrase(), Some(false)), phrase());
+        assert_eq!(All.resolve(phrase(), Some(false)), None);
+        assert_eq!(PrdOnly.resolve(phrase(), Some(false)), None);
+        assert_eq!(PhraseOnly.resolve(phrase(), Some(false)), phrase());
+
+        // Every story passing without the phrase
+        assert_eq!(
+            Any.resolve(None, Some(true)),
+            Some(CompletionReason::AllStoriesComplete)
+        );
+        assert_eq!(All.resolve(None, Some(true)), None);
+        assert_eq!(
+            PrdOnly.resolve(None, Some(true)),
+            Some(CompletionReason::AllStoriesComplete)
+        );
+        assert_eq!(PhraseOnly.resolve(None, Some(true)), None);
+
+        // Both signals
+        assert_eq!(
+            Any.resolve(phrase(), Some(true)),
+            Some(CompletionReason::Both)
+        );
+        assert_eq!(
+            All.resolve(phrase(), Some(true)),
+            Some(CompletionReason::Both)
+        );
+    }
+
+    #[test]
+    fn test_completion_policy_parse_roundtrip() {
+        for policy in [
+            CompletionPolicy::Any,
+            CompletionPolicy::All,
+            CompletionPolicy::PrdOnly,
+            CompletionPolicy::PhraseOnly,
+        ] {
+            assert_eq!(policy.to_string().parse::<CompletionPolicy>(), Ok(policy));
+        }
+        assert!("sometimes".parse::<CompletionPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_agent_stop_signal() {
+        assert_eq!(
+            agent_stop_signal(&output(
+                "Checking deploy config\n  <promise>BLOCKED: missing AWS credentials</promise>\n"
+            )),
+            Some(StopReason::AgentBlocked {
+                reason: "missing AWS credentials".to_string()
+            })
+        );
+        assert_eq!(
+            agent_stop_signal(&output(
+                "<promise>NEEDS_INPUT: Should the API be versioned?</promise>"
+            )),
+            Some(StopReason::AwaitingInput {
+                question: "Should the API be versioned?".to_string()
+            })
+        );
+        // Quoted inside other text, e.g. an echoed prompt
+        assert_eq!(
+            agent_stop_signal(&output(
+                "If stuck, output <promise>BLOCKED: reason</promise> on a line by itself."
+            )),
+            None
+        );
+        assert_eq!(
+            agent_stop_signal(&output("<promise>COMPLETE</promise>")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_phrase_with_nonce() {
+        let run_id = Uuid::from_u128(0x0186a3d0_5591_460b_ad00_f14f8c1d3f9a);
+        assert_eq!(
+            phrase_with_nonce("<promise>COMPLETE</promise>", run_id),
+            "<promise>COMPLETE-3f9a</promise>"
+        );
+        assert_eq!(phrase_with_nonce("ALL DONE", run_id), "ALL DONE-3f9a");
+    }
+}
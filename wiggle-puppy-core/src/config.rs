@@ -4,8 +4,14 @@
 //! for configuring the agent command, iteration limits, delays,
 //! completion detection, and prompt handling.
 
+use crate::agent::{PromptDelivery, DEFAULT_KILL_GRACE_SECS, DEFAULT_USAGE_LIMIT_WAIT_SECS};
+use crate::completion::{CompletionDetector, CompletionPolicy};
 use crate::error::{Error, Result};
-use std::path::PathBuf;
+use crate::hook::Hook;
+use crate::pattern::{ErrorClass, ErrorPattern};
+use crate::template::{self, TemplateVars};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Default agent command.
@@ -23,8 +29,16 @@ const DEFAULT_DELAY_SECS: u64 = 2;
 /// Default completion phrase to detect.
 const DEFAULT_COMPLETION_PHRASE: &str = "<promise>COMPLETE</promise>";
 
-/// Default auto-completion instruction appended to prompts.
-const DEFAULT_COMPLETION_INSTRUCTION: &str = "\n\nIMPORTANT: When you have completed ALL tasks in this prompt and there is nothing left to do, output exactly: <promise>COMPLETE</promise>\nDo NOT output this phrase until every single task is fully complete. Only output it once at the very end.";
+/// Build the auto-completion instruction appended to prompts.
+///
+/// The phrase stays inline with other text so that an agent echoing its
+/// prompt never produces it on a line by itself.
+fn completion_instruction(phrase: &str) -> String {
+    format!(
+        "\n\nIMPORTANT: When you have completed ALL tasks in this prompt and there is nothing left to do, output exactly {} on a line by itself.\nDo NOT output this phrase until every single task is fully complete. Only output it once at the very end.\nIf you are blocked and cannot make progress (for example, missing credentials), instead output <promise>BLOCKED: reason</promise> on a line by itself. If you need an answer from the user before continuing, output <promise>NEEDS_INPUT: your question</promise> on a line by itself.",
+        phrase
+    )
+}
 
 /// Default agent execution timeout in seconds (15 minutes).
 const DEFAULT_AGENT_TIMEOUT_SECS: u64 = 900;
@@ -41,16 +55,57 @@ const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2.0;
 /// Default circuit breaker threshold (stop after N consecutive failures).
 const DEFAULT_CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
 
-/// Default error patterns that indicate Claude Code failure.
-fn default_error_patterns() -> Vec<String> {
+/// Default minimum wait in seconds after a rate-limited error pattern.
+const DEFAULT_RATE_LIMIT_BACKOFF_SECS: u64 = 120;
+
+/// Default number of consecutive usage limit waits before the run stops.
+const DEFAULT_MAX_USAGE_LIMIT_WAITS: u32 = 6;
+
+/// Default verify command timeout in seconds (10 minutes).
+const DEFAULT_VERIFY_TIMEOUT_SECS: u64 = 600;
+
+/// Default header of the previous-iteration feedback section.
+const DEFAULT_FEEDBACK_HEADER: &str = "## Previous iteration";
+
+/// Default lines of agent output carried in the feedback section.
+const DEFAULT_FEEDBACK_OUTPUT_LINES: usize = 20;
+
+/// Default maximum size of the feedback section in characters.
+const DEFAULT_FEEDBACK_MAX_CHARS: usize = 4000;
+
+/// Default error patterns that indicate Claude Code failure, classified by how to react.
+fn default_error_patterns() -> Vec<ErrorPattern> {
+    let literal = |text: &str, class| ErrorPattern::literal(text, class);
     vec![
-        "Error: No messages returned".to_string(),
-        "This error originated either by throwing inside of an async function".to_string(),
-        "@anthropic-ai/claude-code".to_string(),
-        "The promise rejected with the reason:".to_string(),
+        literal("Error: No messages returned", ErrorClass::Transient),
+        literal(
+            "This error originated either by throwing inside of an async function",
+            ErrorClass::Transient,
+        ),
+        literal("@anthropic-ai/claude-code", ErrorClass::Transient),
+        literal(
+            "The promise rejected with the reason:",
+            ErrorClass::Transient,
+        ),
+        literal("rate_limit_error", ErrorClass::RateLimited),
+        literal("overloaded_error", ErrorClass::RateLimited),
+        ErrorPattern::new(r"API Error: (429|529)\b", ErrorClass::RateLimited)
+            .expect("default pattern is a valid regex"),
+        literal("authentication_error", ErrorClass::Fatal),
+        literal("Invalid API key", ErrorClass::Fatal),
+        literal("Please run /login", ErrorClass::Fatal),
     ]
 }
 
+/// One part of the prompt, concatenated with the others in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptSource {
+    /// A prompt file, re-read each iteration with its includes expanded.
+    File(PathBuf),
+    /// Inline text; includes resolve against the working directory.
+    Text(String),
+}
+
 /// Configuration for the Wiggle Puppy runner.
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -60,35 +115,102 @@ pub struct Config {
     /// Arguments to pass to the agent command.
     pub agent_args: Vec<String>,
 
+    /// How the prompt is handed to the agent process.
+    pub prompt_delivery: PromptDelivery,
+
     /// Maximum number of iterations before stopping.
     pub max_iterations: u32,
 
     /// Delay between iterations.
     pub delay: Duration,
 
-    /// Phrase that signals completion when detected in output.
+    /// Phrase that signals completion when it appears on a line by itself.
     pub completion_phrase: String,
 
+    /// Whether to add a per-run nonce to the completion phrase.
+    ///
+    /// The phrase `<promise>COMPLETE</promise>` becomes e.g.
+    /// `<promise>COMPLETE-3f9a</promise>`, so only an agent that read this
+    /// run's instruction can produce it.
+    pub completion_nonce: bool,
+
+    /// Completion detectors checked against agent output, in order.
+    ///
+    /// When empty, completion is detected by `completion_phrase`.
+    pub completion_detectors: Vec<Box<dyn CompletionDetector>>,
+
+    /// How the agent's completion signal combines with the PRD.
+    pub completion_policy: CompletionPolicy,
+
     /// Path to the PRD JSON file (optional).
     pub prd_path: Option<PathBuf>,
 
-    /// Path to the prompt file (optional if prompt_text is set).
-    pub prompt_path: Option<PathBuf>,
+    /// Prompt files and inline text, concatenated in order.
+    pub prompt_sources: Vec<PromptSource>,
+
+    /// Custom variables for the prompt template (see the `template` module).
+    pub template_vars: BTreeMap<String, String>,
 
-    /// Inline prompt text (optional if prompt_path is set).
-    pub prompt_text: Option<String>,
+    /// Whether to expand includes and render variables in the prompt.
+    pub prompt_templating: bool,
 
     /// Path to the progress log file (optional).
     pub progress_path: Option<PathBuf>,
 
+    /// Path to the checkpoint file, rewritten after each iteration (optional).
+    pub checkpoint_path: Option<PathBuf>,
+
+    /// Whether to continue from an existing checkpoint instead of starting over.
+    pub resume: bool,
+
+    /// Whether to resume even if the checkpoint was written with different settings.
+    pub force_resume: bool,
+
     /// Whether to append the auto-completion instruction to prompts.
     pub auto_completion_instruction: bool,
 
-    /// Agent execution timeout in seconds.
+    /// Agent execution timeout in seconds, covering the whole run.
     pub agent_timeout_secs: u64,
 
-    /// Error patterns that indicate Claude Code failure.
-    pub error_patterns: Vec<String>,
+    /// Seconds without agent output before it is killed (0 = disabled).
+    pub agent_idle_timeout_secs: u64,
+
+    /// Seconds to wait after SIGTERM before SIGKILL when the run is cancelled.
+    pub agent_kill_grace_secs: u64,
+
+    /// Hooks run in order before each agent invocation.
+    pub pre_iteration_hooks: Vec<Hook>,
+
+    /// Hooks run in order after each agent invocation.
+    pub post_iteration_hooks: Vec<Hook>,
+
+    /// Shell command that must pass before completion is accepted (optional).
+    pub verify_command: Option<String>,
+
+    /// Verify command timeout in seconds (0 = no timeout).
+    pub verify_timeout_secs: u64,
+
+    /// Whether to add a section about the previous iteration to each prompt.
+    pub iteration_feedback: bool,
+
+    /// Header line of the previous-iteration feedback section.
+    pub feedback_header: String,
+
+    /// Lines of the previous iteration's output in the feedback section.
+    pub feedback_output_lines: usize,
+
+    /// Maximum size of the feedback section in characters (0 = unlimited).
+    pub feedback_max_chars: usize,
+
+    /// Classified error patterns that indicate Claude Code failure.
+    pub error_patterns: Vec<ErrorPattern>,
+
+    /// Seconds to sleep after a usage limit message that carries no reset
+    /// time, or one that is not in the future.
+    pub usage_limit_wait_secs: u64,
+
+    /// Consecutive usage limit waits before the run stops (0 = unlimited).
+    pub max_usage_limit_waits: u32,
 
     /// Maximum retry attempts after error/timeout.
     pub max_retries: u32,
@@ -99,8 +221,17 @@ pub struct Config {
     /// Backoff multiplier.
     pub backoff_multiplier: f64,
 
+    /// Minimum wait in seconds before retrying after a rate-limited error pattern.
+    pub rate_limit_backoff_secs: u64,
+
     /// Circuit breaker threshold (stop after N consecutive failures, 0=disabled).
     pub circuit_breaker_threshold: u32,
+
+    /// Stop after N consecutive iterations without progress (0 = disabled).
+    pub stall_threshold: u32,
+
+    /// Directory watched for file changes by stall detection (optional).
+    pub stall_workspace: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -111,20 +242,43 @@ impl Default for Config {
                 .split_whitespace()
                 .map(String::from)
                 .collect(),
+            prompt_delivery: PromptDelivery::default(),
             max_iterations: DEFAULT_MAX_ITERATIONS,
             delay: Duration::from_secs(DEFAULT_DELAY_SECS),
             completion_phrase: DEFAULT_COMPLETION_PHRASE.to_string(),
+            completion_nonce: false,
+            completion_detectors: Vec::new(),
+            completion_policy: CompletionPolicy::default(),
             prd_path: None,
-            prompt_path: None,
-            prompt_text: None,
+            prompt_sources: Vec::new(),
+            template_vars: BTreeMap::new(),
+            prompt_templating: true,
             progress_path: None,
+            checkpoint_path: None,
+            resume: false,
+            force_resume: false,
             auto_completion_instruction: true,
             agent_timeout_secs: DEFAULT_AGENT_TIMEOUT_SECS,
+            agent_idle_timeout_secs: 0,
+            agent_kill_grace_secs: DEFAULT_KILL_GRACE_SECS,
+            pre_iteration_hooks: Vec::new(),
+            post_iteration_hooks: Vec::new(),
+            verify_command: None,
+            verify_timeout_secs: DEFAULT_VERIFY_TIMEOUT_SECS,
+            iteration_feedback: false,
+            feedback_header: DEFAULT_FEEDBACK_HEADER.to_string(),
+            feedback_output_lines: DEFAULT_FEEDBACK_OUTPUT_LINES,
+            feedback_max_chars: DEFAULT_FEEDBACK_MAX_CHARS,
             error_patterns: default_error_patterns(),
+            usage_limit_wait_secs: DEFAULT_USAGE_LIMIT_WAIT_SECS,
+            max_usage_limit_waits: DEFAULT_MAX_USAGE_LIMIT_WAITS,
             max_retries: DEFAULT_MAX_RETRIES,
             initial_backoff_secs: DEFAULT_INITIAL_BACKOFF_SECS,
             backoff_multiplier: DEFAULT_BACKOFF_MULTIPLIER,
+            rate_limit_backoff_secs: DEFAULT_RATE_LIMIT_BACKOFF_SECS,
             circuit_breaker_threshold: DEFAULT_CIRCUIT_BREAKER_THRESHOLD,
+            stall_threshold: 0,
+            stall_workspace: None,
         }
     }
 }
@@ -153,6 +307,12 @@ impl Config {
         self
     }
 
+    /// Set how the prompt is handed to the agent process.
+    pub fn prompt_delivery(mut self, delivery: PromptDelivery) -> Self {
+        self.prompt_delivery = delivery;
+        self
+    }
+
     /// Set the maximum number of iterations.
     pub fn max_iterations(mut self, max: u32) -> Self {
         self.max_iterations = max;
@@ -177,21 +337,88 @@ impl Config {
         self
     }
 
+    /// Enable or disable the per-run completion phrase nonce.
+    pub fn completion_nonce(mut self, enabled: bool) -> Self {
+        self.completion_nonce = enabled;
+        self
+    }
+
+    /// Set the completion detectors, replacing the completion phrase check.
+    pub fn completion_detectors(mut self, detectors: Vec<Box<dyn CompletionDetector>>) -> Self {
+        self.completion_detectors = detectors;
+        self
+    }
+
+    /// Add a completion detector to the end of the list.
+    ///
+    /// Once any detector is configured, the completion phrase alone no
+    /// longer signals completion; add a `SubstringDetector` for it if needed.
+    pub fn add_completion_detector(mut self, detector: impl CompletionDetector + 'static) -> Self {
+        self.completion_detectors.push(Box::new(detector));
+        self
+    }
+
+    /// Set the completion policy.
+    ///
+    /// `PrdOnly` requires a PRD; the runner refuses to start without one.
+    pub fn completion_policy(mut self, policy: CompletionPolicy) -> Self {
+        self.completion_policy = policy;
+        self
+    }
+
     /// Set the PRD file path.
     pub fn prd_path(mut self, path: impl Into<PathBuf>) -> Self {
         self.prd_path = Some(path.into());
         self
     }
 
-    /// Set the prompt file path.
+    /// Set the prompt file path, replacing any other prompt files.
+    ///
+    /// Inline text is kept after the file, so this and `prompt_text` give
+    /// the same prompt in either order.
     pub fn prompt_path(mut self, path: impl Into<PathBuf>) -> Self {
-        self.prompt_path = Some(path.into());
+        self.prompt_sources
+            .retain(|source| matches!(source, PromptSource::Text(_)));
+        self.prompt_sources
+            .insert(0, PromptSource::File(path.into()));
         self
     }
 
-    /// Set the inline prompt text.
+    /// Add a prompt file after the rest of the prompt.
+    pub fn add_prompt_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.prompt_sources.push(PromptSource::File(path.into()));
+        self
+    }
+
+    /// Set the inline prompt text, replacing any other inline text.
+    ///
+    /// The text goes after the prompt files, so this and `prompt_path` give
+    /// the same prompt in either order.
     pub fn prompt_text(mut self, text: impl Into<String>) -> Self {
-        self.prompt_text = Some(text.into());
+        self.prompt_sources
+            .retain(|source| matches!(source, PromptSource::File(_)));
+        self.prompt_sources.push(PromptSource::Text(text.into()));
+        self
+    }
+
+    /// Add inline prompt text after the rest of the prompt.
+    pub fn add_prompt_text(mut self, text: impl Into<String>) -> Self {
+        self.prompt_sources.push(PromptSource::Text(text.into()));
+        self
+    }
+
+    /// Set a custom prompt template variable, referenced as `{{name}}`.
+    pub fn template_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.template_vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Enable or disable prompt templating.
+    ///
+    /// When disabled the prompt is sent as written, so `{{name}}` and
+    /// `{{> path}}` in it (say, a GitHub Actions workflow) are left alone.
+    pub fn prompt_templating(mut self, enabled: bool) -> Self {
+        self.prompt_templating = enabled;
         self
     }
 
@@ -201,6 +428,27 @@ impl Config {
         self
     }
 
+    /// Set the checkpoint file path.
+    pub fn checkpoint_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// Set whether to continue from an existing checkpoint.
+    ///
+    /// The iteration count and consecutive failure counter carry on from the
+    /// checkpoint, so `max_iterations` remains the budget for the whole run.
+    pub fn resume(mut self, enabled: bool) -> Self {
+        self.resume = enabled;
+        self
+    }
+
+    /// Set whether to resume from a checkpoint written with different settings.
+    pub fn force_resume(mut self, enabled: bool) -> Self {
+        self.force_resume = enabled;
+        self
+    }
+
     /// Enable or disable the auto-completion instruction.
     pub fn auto_completion_instruction(mut self, enabled: bool) -> Self {
         self.auto_completion_instruction = enabled;
@@ -213,15 +461,97 @@ impl Config {
         self
     }
 
+    /// Set the agent idle timeout in seconds (0 to disable).
+    pub fn agent_idle_timeout_secs(mut self, secs: u64) -> Self {
+        self.agent_idle_timeout_secs = secs;
+        self
+    }
+
+    /// Set the seconds to wait after SIGTERM before SIGKILL on cancellation.
+    pub fn agent_kill_grace_secs(mut self, secs: u64) -> Self {
+        self.agent_kill_grace_secs = secs;
+        self
+    }
+
+    /// Set the hooks run before each agent invocation.
+    pub fn pre_iteration_hooks(mut self, hooks: Vec<Hook>) -> Self {
+        self.pre_iteration_hooks = hooks;
+        self
+    }
+
+    /// Add a hook to run before each agent invocation, after any already set.
+    pub fn add_pre_iteration_hook(mut self, hook: Hook) -> Self {
+        self.pre_iteration_hooks.push(hook);
+        self
+    }
+
+    /// Set the hooks run after each agent invocation.
+    pub fn post_iteration_hooks(mut self, hooks: Vec<Hook>) -> Self {
+        self.post_iteration_hooks = hooks;
+        self
+    }
+
+    /// Add a hook to run after each agent invocation, after any already set.
+    pub fn add_post_iteration_hook(mut self, hook: Hook) -> Self {
+        self.post_iteration_hooks.push(hook);
+        self
+    }
+
+    /// Set a shell command that must pass before completion is accepted.
+    ///
+    /// The command runs with `sh -c` whenever the completion phrase is
+    /// detected or the PRD is complete. If it fails, the run continues and
+    /// its output is added to the next prompt.
+    pub fn verify_command(mut self, command: impl Into<String>) -> Self {
+        self.verify_command = Some(command.into());
+        self
+    }
+
+    /// Set the verify command timeout in seconds (0 to disable).
+    pub fn verify_timeout_secs(mut self, secs: u64) -> Self {
+        self.verify_timeout_secs = secs;
+        self
+    }
+
+    /// Enable or disable the previous-iteration feedback section.
+    ///
+    /// When enabled, each prompt after the first ends with a section giving
+    /// the previous iteration's exit code, agent error, retries, failed
+    /// verify output and last lines of output.
+    pub fn iteration_feedback(mut self, enabled: bool) -> Self {
+        self.iteration_feedback = enabled;
+        self
+    }
+
+    /// Set the header line of the feedback section.
+    pub fn feedback_header(mut self, header: impl Into<String>) -> Self {
+        self.feedback_header = header.into();
+        self
+    }
+
+    /// Set how many lines of the previous iteration's output to include.
+    pub fn feedback_output_lines(mut self, lines: usize) -> Self {
+        self.feedback_output_lines = lines;
+        self
+    }
+
+    /// Set the maximum size of the feedback section in characters (0 for no limit).
+    ///
+    /// Output lines are dropped first; if that isn't enough the section is cut off.
+    pub fn feedback_max_chars(mut self, chars: usize) -> Self {
+        self.feedback_max_chars = chars;
+        self
+    }
+
     /// Set the error patterns that indicate Claude Code failure.
-    pub fn error_patterns(mut self, patterns: Vec<String>) -> Self {
+    pub fn error_patterns(mut self, patterns: Vec<ErrorPattern>) -> Self {
         self.error_patterns = patterns;
         self
     }
 
-    /// Add an error pattern to the list.
-    pub fn add_error_pattern(mut self, pattern: impl Into<String>) -> Self {
-        self.error_patterns.push(pattern.into());
+    /// Add an error pattern to the end of the list.
+    pub fn add_error_pattern(mut self, pattern: ErrorPattern) -> Self {
+        self.error_patterns.push(pattern);
         self
     }
 
@@ -231,6 +561,19 @@ impl Config {
         self
     }
 
+    /// Set the sleep in seconds after a usage limit message without a reset time.
+    pub fn usage_limit_wait_secs(mut self, secs: u64) -> Self {
+        self.usage_limit_wait_secs = secs;
+        self
+    }
+
+    /// Set how many consecutive usage limit waits are allowed before the run
+    /// stops (0 = unlimited).
+    pub fn max_usage_limit_waits(mut self, waits: u32) -> Self {
+        self.max_usage_limit_waits = waits;
+        self
+    }
+
     /// Set the maximum retry attempts after error/timeout.
     pub fn max_retries(mut self, max: u32) -> Self {
         self.max_retries = max;
@@ -249,12 +592,36 @@ impl Config {
         self
     }
 
+    /// Set the minimum wait in seconds after a rate-limited error pattern.
+    pub fn rate_limit_backoff_secs(mut self, secs: u64) -> Self {
+        self.rate_limit_backoff_secs = secs;
+        self
+    }
+
     /// Set the circuit breaker threshold (0 to disable).
     pub fn circuit_breaker_threshold(mut self, threshold: u32) -> Self {
         self.circuit_breaker_threshold = threshold;
         self
     }
 
+    /// Set how many consecutive iterations without progress stop the run (0 to disable).
+    ///
+    /// An iteration makes progress if it raises the PRD's completed-story
+    /// count, or changes files in the stall workspace without repeating the
+    /// previous iteration's output almost verbatim.
+    pub fn stall_threshold(mut self, threshold: u32) -> Self {
+        self.stall_threshold = threshold;
+        self
+    }
+
+    /// Set the directory watched for file changes by stall detection.
+    ///
+    /// Without one, only the PRD and the agent's output are considered.
+    pub fn stall_workspace(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stall_workspace = Some(path.into());
+        self
+    }
+
     /// Get a formatted display string for the agent command.
     ///
     /// Returns the command and arguments as they would appear on the command line.
@@ -268,35 +635,87 @@ impl Config {
 
     /// Get the prompt content.
     ///
-    /// If `prompt_path` is set, reads from the file. Otherwise, returns `prompt_text`.
-    /// If `auto_completion_instruction` is enabled, appends the completion instruction.
+    /// Reads each of `prompt_sources` in order, joined by blank lines,
+    /// expanding `{{> path}}` includes unless `prompt_templating` is off. If
+    /// `auto_completion_instruction` is enabled, appends the completion
+    /// instruction. Template variables are left unrendered; the runner
+    /// renders them each iteration.
     ///
     /// # Errors
     ///
-    /// Returns `Error::NoPrompt` if neither prompt_path nor prompt_text is set.
-    /// Returns `Error::PromptReadError` if the prompt file cannot be read.
+    /// Returns `Error::NoPrompt` if `prompt_sources` is empty.
+    /// Returns `Error::PromptReadError` if a prompt file cannot be read.
+    /// Returns `Error::PromptIncludeCycle` if a prompt file includes itself.
     pub fn get_prompt(&self) -> Result<String> {
-        let base_prompt = if let Some(path) = &self.prompt_path {
-            std::fs::read_to_string(path).map_err(|source| Error::PromptReadError {
-                path: path.clone(),
-                source,
-            })?
-        } else if let Some(text) = &self.prompt_text {
-            text.clone()
-        } else {
-            return Err(Error::NoPrompt);
-        };
+        let base_prompt = self.read_prompt()?;
+        Ok(self.with_instruction(base_prompt, &self.completion_phrase))
+    }
+
+    /// Get the prompt rendered as a template with `vars`.
+    ///
+    /// `phrase` is named in the auto-completion instruction, which the
+    /// runner uses to pass the phrase with its per-run nonce. The instruction
+    /// itself is not rendered, and neither is the prompt when
+    /// `prompt_templating` is off.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `get_prompt`, or `Error::PromptTemplateError`
+    /// if the prompt references a variable that isn't in `vars`.
+    pub fn render_prompt(&self, vars: &TemplateVars, phrase: &str) -> Result<String> {
+        let mut base_prompt = self.read_prompt()?;
+        if self.prompt_templating {
+            base_prompt = template::render(&base_prompt, vars)?;
+        }
+        Ok(self.with_instruction(base_prompt, phrase))
+    }
+
+    /// Read the prompt files and inline text, expanding includes if
+    /// templating is on.
+    fn read_prompt(&self) -> Result<String> {
+        let mut parts = Vec::new();
+        for source in &self.prompt_sources {
+            parts.push(match source {
+                PromptSource::File(path) if self.prompt_templating => {
+                    template::read_with_includes(path)?
+                }
+                PromptSource::File(path) => {
+                    std::fs::read_to_string(path).map_err(|source| Error::PromptReadError {
+                        path: path.clone(),
+                        source,
+                    })?
+                }
+                // Includes in inline text resolve against the working directory
+                PromptSource::Text(text) if self.prompt_templating => {
+                    template::expand_includes(text, Path::new(""))?
+                }
+                PromptSource::Text(text) => text.clone(),
+            });
+        }
+
+        match parts.len() {
+            0 => Err(Error::NoPrompt),
+            1 => Ok(parts.remove(0)),
+            _ => Ok(parts
+                .iter()
+                .map(|part| part.trim_end())
+                .collect::<Vec<_>>()
+                .join("\n\n")),
+        }
+    }
 
+    /// Append the auto-completion instruction naming `phrase`, if enabled.
+    fn with_instruction(&self, base_prompt: String, phrase: &str) -> String {
         if self.auto_completion_instruction {
-            Ok(format!("{}{}", base_prompt, DEFAULT_COMPLETION_INSTRUCTION))
+            format!("{}{}", base_prompt, completion_instruction(phrase))
         } else {
-            Ok(base_prompt)
+            base_prompt
         }
     }
 
     /// Check if this config has a prompt configured.
     pub fn has_prompt(&self) -> bool {
-        self.prompt_path.is_some() || self.prompt_text.is_some()
+        !self.prompt_sources.is_empty()
     }
 }
 
@@ -313,6 +732,7 @@ mod tests {
         assert_eq!(config.delay, Duration::from_secs(2));
         assert!(config.completion_phrase.contains("COMPLETE"));
         assert!(config.auto_completion_instruction);
+        assert_eq!(config.prompt_delivery, PromptDelivery::Argument);
     }
 
     #[test]
@@ -323,6 +743,7 @@ mod tests {
             .max_iterations(10)
             .delay_secs(5)
             .completion_phrase("DONE")
+            .prompt_delivery(PromptDelivery::Stdin)
             .auto_completion_instruction(false);
 
         assert_eq!(config.agent_command, "aider");
@@ -330,6 +751,7 @@ mod tests {
         assert_eq!(config.max_iterations, 10);
         assert_eq!(config.delay, Duration::from_secs(5));
         assert_eq!(config.completion_phrase, "DONE");
+        assert_eq!(config.prompt_delivery, PromptDelivery::Stdin);
         assert!(!config.auto_completion_instruction);
     }
 
@@ -366,6 +788,53 @@ mod tests {
         let prompt = config.get_prompt().unwrap();
         assert!(prompt.starts_with("Do something"));
         assert!(prompt.contains("<promise>COMPLETE</promise>"));
+        // Echoing the prompt must not produce the phrase on a line by itself
+        assert!(!prompt
+            .lines()
+            .any(|line| line.trim() == "<promise>COMPLETE</promise>"));
+    }
+
+    #[test]
+    fn test_get_prompt_instruction_uses_configured_phrase() {
+        let config = Config::new()
+            .prompt_text("Do something")
+            .completion_phrase("ALL DONE");
+
+        let prompt = config.get_prompt().unwrap();
+        assert!(prompt.contains("output exactly ALL DONE on a line by itself"));
+        assert!(!prompt.contains("<promise>COMPLETE</promise>"));
+
+        let prompt = config
+            .render_prompt(&TemplateVars::new(), "ALL DONE-3f9a")
+            .unwrap();
+        assert!(prompt.contains("ALL DONE-3f9a"));
+    }
+
+    #[test]
+    fn test_render_prompt() {
+        let config = Config::new()
+            .prompt_text("Iteration {{iteration}} for {{team}}")
+            .template_var("team", "platform")
+            .auto_completion_instruction(false);
+
+        // get_prompt leaves the template as is
+        assert_eq!(
+            config.get_prompt().unwrap(),
+            "Iteration {{iteration}} for {{team}}"
+        );
+
+        let vars: TemplateVars = [("iteration", "2"), ("team", "platform")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            config.render_prompt(&vars, "DONE").unwrap(),
+            "Iteration 2 for platform"
+        );
+
+        let err = config
+            .render_prompt(&TemplateVars::new(), "DONE")
+            .expect_err("unset variables should fail");
+        assert!(matches!(err, Error::PromptTemplateError { .. }));
     }
 
     #[test]
@@ -391,6 +860,113 @@ mod tests {
         std::fs::remove_file(&temp_path).ok();
     }
 
+    #[test]
+    fn test_get_prompt_concatenates_files_and_text() {
+        let dir = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_prompt_files_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("standards.md"), "Write tests.\n").unwrap();
+        std::fs::write(dir.join("task.md"), "{{> standards.md}}\nFix the bug.\n").unwrap();
+        std::fs::write(dir.join("layout.md"), "Code lives in src/.\n").unwrap();
+
+        let config = Config::new()
+            .prompt_path(dir.join("task.md"))
+            .add_prompt_path(dir.join("layout.md"))
+            .add_prompt_text("Be brief.")
+            .auto_completion_instruction(false);
+        assert_eq!(
+            config.get_prompt().unwrap(),
+            "Write tests.\nFix the bug.\n\nCode lives in src/.\n\nBe brief."
+        );
+
+        // prompt_path replaces the files and keeps the text
+        let config = config.prompt_path(dir.join("layout.md"));
+        assert_eq!(
+            config.prompt_sources,
+            vec![
+                PromptSource::File(dir.join("layout.md")),
+                PromptSource::Text("Be brief.".to_string()),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_prompt_path_and_text_in_either_order() {
+        let expected = vec![
+            PromptSource::File(PathBuf::from("task.md")),
+            PromptSource::Text("Be brief.".to_string()),
+        ];
+        let path_first = Config::new()
+            .prompt_path("task.md")
+            .prompt_text("Be brief.");
+        let text_first = Config::new()
+            .prompt_text("Be brief.")
+            .prompt_path("task.md");
+        assert_eq!(path_first.prompt_sources, expected);
+        assert_eq!(text_first.prompt_sources, expected);
+
+        // Each replaces only its own kind
+        let config = path_first
+            .prompt_text("Be thorough.")
+            .prompt_path("other.md");
+        assert_eq!(
+            config.prompt_sources,
+            vec![
+                PromptSource::File(PathBuf::from("other.md")),
+                PromptSource::Text("Be thorough.".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_prompt_keeps_text_in_position() {
+        let dir = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_prompt_order_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.md"), "First.\n").unwrap();
+        std::fs::write(dir.join("b.md"), "Last.\n").unwrap();
+
+        let config = Config::new()
+            .add_prompt_path(dir.join("a.md"))
+            .add_prompt_text("Middle.")
+            .add_prompt_path(dir.join("b.md"))
+            .auto_completion_instruction(false);
+        assert_eq!(config.get_prompt().unwrap(), "First.\n\nMiddle.\n\nLast.");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_render_prompt_keeps_foreign_template_syntax() {
+        let vars = TemplateVars::new();
+
+        // Escaped, with templating on
+        let config = Config::new()
+            .prompt_text("Set token: $\\{{ secrets.X }}")
+            .auto_completion_instruction(false);
+        assert_eq!(
+            config.render_prompt(&vars, "DONE").unwrap(),
+            "Set token: ${{ secrets.X }}"
+        );
+
+        // As written, which only works with templating off
+        let config = Config::new()
+            .prompt_text("Set token: ${{ secrets.X }} and {{> partial}}")
+            .auto_completion_instruction(false);
+        assert!(config.render_prompt(&vars, "DONE").is_err());
+        let config = config.prompt_templating(false);
+        assert_eq!(
+            config.render_prompt(&vars, "DONE").unwrap(),
+            "Set token: ${{ secrets.X }} and {{> partial}}"
+        );
+    }
+
     #[test]
     fn test_has_prompt() {
         let config = Config::new();
@@ -416,23 +992,39 @@ mod tests {
     #[test]
     fn test_default_error_patterns() {
         let config = Config::default();
-        assert!(!config.error_patterns.is_empty());
-        assert!(config
-            .error_patterns
-            .iter()
-            .any(|p| p.contains("No messages returned")));
-        assert!(config
-            .error_patterns
-            .iter()
-            .any(|p| p.contains("async function")));
-        assert!(config
-            .error_patterns
-            .iter()
-            .any(|p| p.contains("@anthropic-ai/claude-code")));
-        assert!(config
-            .error_patterns
-            .iter()
-            .any(|p| p.contains("promise rejected")));
+        let class_of = |line: &str| {
+            config
+                .error_patterns
+                .iter()
+                .find(|p| p.is_match(line))
+                .map(|p| p.class())
+        };
+
+        assert_eq!(
+            class_of("Error: No messages returned"),
+            Some(ErrorClass::Transient)
+        );
+        assert_eq!(
+            class_of("This error originated either by throwing inside of an async function"),
+            Some(ErrorClass::Transient)
+        );
+        assert_eq!(
+            class_of("at file:///usr/lib/node_modules/@anthropic-ai/claude-code/cli.js"),
+            Some(ErrorClass::Transient)
+        );
+        assert_eq!(
+            class_of("The promise rejected with the reason: oops"),
+            Some(ErrorClass::Transient)
+        );
+        assert_eq!(
+            class_of("API Error: 529 {\"type\":\"overloaded_error\"}"),
+            Some(ErrorClass::RateLimited)
+        );
+        assert_eq!(
+            class_of("Invalid API key · Please run /login"),
+            Some(ErrorClass::Fatal)
+        );
+        assert_eq!(class_of("Implemented story 3"), None);
     }
 
     #[test]
@@ -441,18 +1033,25 @@ mod tests {
         assert_eq!(config.agent_timeout_secs, 1800);
     }
 
+    #[test]
+    fn test_agent_idle_timeout_secs_builder() {
+        assert_eq!(Config::default().agent_idle_timeout_secs, 0);
+        let config = Config::new().agent_idle_timeout_secs(120);
+        assert_eq!(config.agent_idle_timeout_secs, 120);
+    }
+
     #[test]
     fn test_error_patterns_builder() {
-        let config = Config::new().error_patterns(vec!["custom error".to_string()]);
-        assert_eq!(config.error_patterns, vec!["custom error"]);
+        let pattern = ErrorPattern::literal("custom error", ErrorClass::Transient);
+        let config = Config::new().error_patterns(vec![pattern.clone()]);
+        assert_eq!(config.error_patterns, vec![pattern]);
     }
 
     #[test]
     fn test_add_error_pattern_builder() {
-        let config = Config::new().add_error_pattern("additional error");
-        assert!(config
-            .error_patterns
-            .contains(&"additional error".to_string()));
+        let pattern = ErrorPattern::literal("additional error", ErrorClass::Fatal);
+        let config = Config::new().add_error_pattern(pattern.clone());
+        assert!(config.error_patterns.contains(&pattern));
         // Should still have default patterns plus the new one
         assert!(config.error_patterns.len() > 1);
     }
@@ -463,6 +1062,115 @@ mod tests {
         assert!(config.error_patterns.is_empty());
     }
 
+    #[test]
+    fn test_agent_kill_grace_secs_builder() {
+        assert_eq!(Config::default().agent_kill_grace_secs, 5);
+        let config = Config::new().agent_kill_grace_secs(1);
+        assert_eq!(config.agent_kill_grace_secs, 1);
+    }
+
+    #[test]
+    fn test_completion_nonce_builder() {
+        assert!(!Config::default().completion_nonce);
+        assert!(Config::new().completion_nonce(true).completion_nonce);
+    }
+
+    #[test]
+    fn test_completion_policy_builder() {
+        assert_eq!(Config::default().completion_policy, CompletionPolicy::Any);
+        let config = Config::new().completion_policy(CompletionPolicy::PrdOnly);
+        assert_eq!(config.completion_policy, CompletionPolicy::PrdOnly);
+    }
+
+    #[test]
+    fn test_completion_detector_builders() {
+        use crate::completion::{RegexDetector, SentinelLineDetector};
+
+        assert!(Config::default().completion_detectors.is_empty());
+
+        let config = Config::new()
+            .add_completion_detector(SentinelLineDetector::new("DONE"))
+            .add_completion_detector(RegexDetector::new("^finished$").unwrap());
+        let names: Vec<String> = config
+            .completion_detectors
+            .iter()
+            .map(|d| d.name())
+            .collect();
+        assert_eq!(names, vec!["sentinel line \"DONE\"", "regex `^finished$`"]);
+
+        // Cloning the config clones the detectors
+        let cloned = config.clone();
+        assert_eq!(cloned.completion_detectors.len(), 2);
+
+        let config = config.completion_detectors(Vec::new());
+        assert!(config.completion_detectors.is_empty());
+    }
+
+    #[test]
+    fn test_hook_builders() {
+        let config = Config::default();
+        assert!(config.pre_iteration_hooks.is_empty());
+        assert!(config.post_iteration_hooks.is_empty());
+
+        let config = Config::new()
+            .add_pre_iteration_hook(Hook::new("git pull"))
+            .add_post_iteration_hook(Hook::new("cargo fmt"))
+            .add_post_iteration_hook(Hook::new("cargo clippy"));
+        assert_eq!(config.pre_iteration_hooks, vec![Hook::new("git pull")]);
+        let post: Vec<&str> = config
+            .post_iteration_hooks
+            .iter()
+            .map(|h| h.command.as_str())
+            .collect();
+        assert_eq!(post, vec!["cargo fmt", "cargo clippy"]);
+
+        let config = config.post_iteration_hooks(Vec::new());
+        assert!(config.post_iteration_hooks.is_empty());
+    }
+
+    #[test]
+    fn test_verify_command_builder() {
+        let config = Config::default();
+        assert!(config.verify_command.is_none());
+        assert_eq!(config.verify_timeout_secs, 600);
+
+        let config = Config::new()
+            .verify_command("cargo test")
+            .verify_timeout_secs(60);
+        assert_eq!(config.verify_command.as_deref(), Some("cargo test"));
+        assert_eq!(config.verify_timeout_secs, 60);
+    }
+
+    #[test]
+    fn test_iteration_feedback_builders() {
+        let config = Config::default();
+        assert!(!config.iteration_feedback);
+        assert_eq!(config.feedback_header, "## Previous iteration");
+        assert_eq!(config.feedback_output_lines, 20);
+        assert_eq!(config.feedback_max_chars, 4000);
+
+        let config = Config::new()
+            .iteration_feedback(true)
+            .feedback_header("# Last attempt")
+            .feedback_output_lines(5)
+            .feedback_max_chars(0);
+        assert!(config.iteration_feedback);
+        assert_eq!(config.feedback_header, "# Last attempt");
+        assert_eq!(config.feedback_output_lines, 5);
+        assert_eq!(config.feedback_max_chars, 0);
+    }
+
+    #[test]
+    fn test_usage_limit_wait_secs_builder() {
+        assert_eq!(Config::default().usage_limit_wait_secs, 3600);
+        let config = Config::new().usage_limit_wait_secs(1800);
+        assert_eq!(config.usage_limit_wait_secs, 1800);
+
+        assert_eq!(Config::default().max_usage_limit_waits, 6);
+        let config = Config::new().max_usage_limit_waits(2);
+        assert_eq!(config.max_usage_limit_waits, 2);
+    }
+
     #[test]
     fn test_max_retries_builder() {
         let config = Config::new().max_retries(5);
@@ -481,6 +1189,43 @@ mod tests {
         assert!((config.backoff_multiplier - 3.0).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn test_rate_limit_backoff_secs_builder() {
+        assert_eq!(Config::default().rate_limit_backoff_secs, 120);
+        let config = Config::new().rate_limit_backoff_secs(600);
+        assert_eq!(config.rate_limit_backoff_secs, 600);
+    }
+
+    #[test]
+    fn test_checkpoint_builders() {
+        let config = Config::default();
+        assert!(config.checkpoint_path.is_none());
+        assert!(!config.resume);
+        assert!(!config.force_resume);
+
+        let config = Config::new()
+            .checkpoint_path("/tmp/checkpoint.json")
+            .resume(true)
+            .force_resume(true);
+        assert_eq!(
+            config.checkpoint_path,
+            Some(PathBuf::from("/tmp/checkpoint.json"))
+        );
+        assert!(config.resume);
+        assert!(config.force_resume);
+    }
+
+    #[test]
+    fn test_stall_builders() {
+        let config = Config::default();
+        assert_eq!(config.stall_threshold, 0);
+        assert!(config.stall_workspace.is_none());
+
+        let config = Config::new().stall_threshold(4).stall_workspace(".");
+        assert_eq!(config.stall_threshold, 4);
+        assert_eq!(config.stall_workspace, Some(PathBuf::from(".")));
+    }
+
     #[test]
     fn test_circuit_breaker_threshold_builder() {
         let config = Config::new().circuit_breaker_threshold(10);
@@ -500,13 +1245,14 @@ mod tests {
             .backoff_multiplier(1.5)
             .circuit_breaker_threshold(3)
             .no_error_patterns()
-            .add_error_pattern("my error");
+            .add_error_pattern(ErrorPattern::literal("my error", ErrorClass::Transient));
 
         assert_eq!(config.agent_timeout_secs, 600);
         assert_eq!(config.max_retries, 5);
         assert_eq!(config.initial_backoff_secs, 10);
         assert!((config.backoff_multiplier - 1.5).abs() < f64::EPSILON);
         assert_eq!(config.circuit_breaker_threshold, 3);
-        assert_eq!(config.error_patterns, vec!["my error"]);
+        assert_eq!(config.error_patterns.len(), 1);
+        assert_eq!(config.error_patterns[0].as_str(), "my error");
     }
 }
@@ -4,6 +4,8 @@
 //! wiggle-puppy-core library, including PRD parsing, agent execution,
 //! configuration, and prompt handling.
 
+use crate::pattern::ErrorClass;
+use chrono::{DateTime, Utc};
 use std::path::PathBuf;
 use thiserror::Error;
 
@@ -50,6 +52,90 @@ pub enum Error {
         source: std::io::Error,
     },
 
+    /// A prompt file includes itself, directly or through other files.
+    #[error("prompt include cycle: '{path}' includes itself")]
+    PromptIncludeCycle {
+        /// The file whose include closes the cycle.
+        path: PathBuf,
+    },
+
+    /// The prompt template references a variable that isn't set.
+    #[error("prompt template references unknown variable '{variable}' on line {line}")]
+    PromptTemplateError {
+        /// The unknown variable name.
+        variable: String,
+        /// The 1-indexed line of the template it appears on.
+        line: usize,
+    },
+
+    /// Failed to write the progress log.
+    #[error("failed to write progress log '{path}': {source}")]
+    ProgressWriteError {
+        /// The path that could not be written.
+        path: PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Failed to read the checkpoint file from disk.
+    #[error("failed to read checkpoint file '{path}': {source}")]
+    CheckpointReadError {
+        /// The path that could not be read.
+        path: PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Failed to parse the checkpoint JSON content.
+    #[error("failed to parse checkpoint JSON from '{path}': {source}")]
+    CheckpointParseError {
+        /// The path containing invalid JSON.
+        path: PathBuf,
+        /// The underlying JSON parse error.
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// Failed to write the checkpoint file to disk.
+    #[error("failed to write checkpoint file '{path}': {source}")]
+    CheckpointWriteError {
+        /// The path that could not be written.
+        path: PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The checkpoint file is in a format this version can't resume from.
+    #[error(
+        "checkpoint '{path}' has format version {found}, expected {expected}; \
+         start a fresh run without resume"
+    )]
+    CheckpointVersionMismatch {
+        /// The checkpoint path.
+        path: PathBuf,
+        /// The format version this build reads and writes.
+        expected: u32,
+        /// The format version recorded in the checkpoint.
+        found: u32,
+    },
+
+    /// The checkpoint was written by a run with different settings.
+    #[error(
+        "checkpoint '{path}' belongs to a run with different settings \
+         (config hash {found}, expected {expected}); use force resume to continue anyway"
+    )]
+    CheckpointMismatch {
+        /// The checkpoint path.
+        path: PathBuf,
+        /// Hash of the current configuration.
+        expected: String,
+        /// Hash recorded in the checkpoint.
+        found: String,
+    },
+
     /// The agent process encountered an error during execution.
     #[error("agent execution failed: {message}")]
     AgentError {
@@ -62,6 +148,38 @@ pub enum Error {
     AgentErrorDetected {
         /// The pattern that was detected.
         pattern: String,
+        /// The class of the detected pattern.
+        class: ErrorClass,
+    },
+
+    /// An error pattern is not a valid regular expression.
+    #[error("invalid error pattern '{pattern}': {source}")]
+    InvalidErrorPattern {
+        /// The pattern that failed to compile.
+        pattern: String,
+        /// The underlying regex error.
+        #[source]
+        source: regex::Error,
+    },
+
+    /// A shell command (such as the verify command) could not be started.
+    #[error("failed to run command '{command}': {source}")]
+    CommandSpawnError {
+        /// The command that could not be started.
+        command: String,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A completion detector regex is not a valid regular expression.
+    #[error("invalid completion pattern '{pattern}': {source}")]
+    InvalidCompletionPattern {
+        /// The pattern that failed to compile.
+        pattern: String,
+        /// The underlying regex error.
+        #[source]
+        source: regex::Error,
     },
 
     /// The agent timed out during execution.
@@ -71,6 +189,20 @@ pub enum Error {
         timeout_secs: u64,
     },
 
+    /// The agent produced no output for longer than the idle timeout.
+    #[error("agent produced no output for {idle_secs} seconds")]
+    AgentIdleTimeout {
+        /// The idle timeout in seconds.
+        idle_secs: u64,
+    },
+
+    /// The agent hit its usage limit.
+    #[error("agent usage limit reached; resuming at {resume_at}")]
+    UsageLimitReached {
+        /// When the quota is expected to reset.
+        resume_at: DateTime<Utc>,
+    },
+
     /// The configured agent command was not found.
     #[error("agent command not found: '{command}'")]
     AgentNotFound {
@@ -109,10 +241,11 @@ impl Error {
         }
     }
 
-    /// Create a new `AgentErrorDetected` error for the given pattern.
-    pub fn agent_error_detected(pattern: impl Into<String>) -> Self {
+    /// Create a new `AgentErrorDetected` error for the given pattern and class.
+    pub fn agent_error_detected(pattern: impl Into<String>, class: ErrorClass) -> Self {
         Self::AgentErrorDetected {
             pattern: pattern.into(),
+            class,
         }
     }
 
@@ -121,6 +254,11 @@ impl Error {
         Self::AgentTimeout { timeout_secs }
     }
 
+    /// Create a new `AgentIdleTimeout` error for the given idle duration.
+    pub fn agent_idle_timeout(idle_secs: u64) -> Self {
+        Self::AgentIdleTimeout { idle_secs }
+    }
+
     /// Create a new `AgentNotFound` error for the given command.
     pub fn agent_not_found(command: impl Into<String>) -> Self {
         Self::AgentNotFound {
@@ -164,7 +302,7 @@ mod tests {
         let err = Error::agent_not_found("nonexistent-agent");
         assert!(err.to_string().contains("nonexistent-agent"));
 
-        let err = Error::agent_error_detected("FATAL ERROR");
+        let err = Error::agent_error_detected("FATAL ERROR", ErrorClass::Fatal);
         assert!(err.to_string().contains("agent error pattern detected"));
         assert!(err.to_string().contains("FATAL ERROR"));
 
@@ -173,6 +311,11 @@ mod tests {
             .to_string()
             .contains("agent timed out after 300 seconds"));
 
+        let err = Error::agent_idle_timeout(120);
+        assert!(err
+            .to_string()
+            .contains("agent produced no output for 120 seconds"));
+
         let err = Error::config_error("invalid max_iterations");
         assert!(err.to_string().contains("invalid max_iterations"));
 
@@ -4,14 +4,40 @@
 //! state changes from the runner to consumers (CLI, TUI). All lifecycle
 //! events, agent output, and status updates are communicated through
 //! this channel-based system.
-
+//!
+//! Each event is delivered in an `EventEnvelope` stamped by the
+//! `EventSender` with a sequence number, the time it was sent, the run id and
+//! the iteration and attempt the runner was on, so consumers can order
+//! events, compute durations and correlate logs across runs.
+//!
+//! Events, completion and stop reasons serialize to JSON objects tagged by a
+//! snake_case `type` field, e.g. `{"type": "iteration_started", "iteration":
+//! 1, "max_iterations": 20}`. `EVENT_SCHEMA_VERSION` is bumped whenever that
+//! schema changes incompatibly.
+
+use crate::hook::HookStage;
+use crate::pattern::ErrorClass;
+use crate::prd::StoryChange;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use uuid::Uuid;
+
+/// Version of the JSON event schema.
+///
+/// Renaming or removing a variant or field, or changing a field's type,
+/// bumps the version; adding variants or fields does not.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
 
 /// Default channel buffer size.
 const DEFAULT_CHANNEL_SIZE: usize = 100;
 
 /// Events emitted by the runner during execution.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Event {
     /// The runner has started.
     Started {
@@ -19,6 +45,14 @@ pub enum Event {
         max_iterations: u32,
     },
 
+    /// The run continues from a checkpoint.
+    RunResumed {
+        /// Identifier of the resumed run, also stamped on its events.
+        run_id: Uuid,
+        /// Iterations already completed before the restart.
+        iteration: u32,
+    },
+
     /// A new iteration is starting.
     IterationStarted {
         /// The current iteration number (1-indexed).
@@ -43,10 +77,16 @@ pub enum Event {
         duration_secs: f64,
     },
 
-    /// Claude Code error pattern detected in output.
+    /// Claude Code error pattern detected in output; the agent is killed immediately.
     AgentErrorDetected {
         /// The error pattern that was detected.
         pattern: String,
+        /// The class of the detected pattern.
+        class: ErrorClass,
+        /// The output line that matched.
+        line: String,
+        /// Line number of the match within the combined output (1-indexed).
+        line_number: usize,
     },
 
     /// Agent process timed out and was killed.
@@ -55,6 +95,31 @@ pub enum Event {
         timeout_secs: u64,
     },
 
+    /// Agent produced no output for too long and was killed.
+    AgentIdleTimeout {
+        /// The idle timeout in seconds.
+        idle_secs: u64,
+    },
+
+    /// The agent hit its usage limit; the runner sleeps until the quota resets.
+    RateLimited {
+        /// When the runner will resume.
+        resume_at: DateTime<Utc>,
+    },
+
+    /// The run was paused, either between iterations or by suspending the agent.
+    Paused {
+        /// Whether the running agent was suspended (SIGSTOP) rather than
+        /// the runner waiting between iterations.
+        agent_suspended: bool,
+    },
+
+    /// The run resumed after a pause.
+    Resumed {
+        /// How long the run was paused, in seconds.
+        paused_secs: f64,
+    },
+
     /// Retry is being scheduled.
     RetryScheduled {
         /// Backoff duration in seconds before retry.
@@ -73,6 +138,30 @@ pub enum Event {
         story_title: String,
     },
 
+    /// A story that was passing is no longer marked as passing.
+    StoryReopened {
+        /// The story ID.
+        story_id: String,
+        /// The story title.
+        story_title: String,
+    },
+
+    /// A story was added to the PRD.
+    StoryAdded {
+        /// The story ID.
+        story_id: String,
+        /// The story title.
+        story_title: String,
+    },
+
+    /// A story was removed from the PRD.
+    StoryRemoved {
+        /// The story ID.
+        story_id: String,
+        /// The story title.
+        story_title: String,
+    },
+
     /// An iteration has finished.
     IterationFinished {
         /// The iteration number that finished.
@@ -81,6 +170,62 @@ pub enum Event {
         completion_detected: bool,
     },
 
+    /// A pre- or post-iteration hook is starting.
+    HookStarted {
+        /// When the hook runs.
+        stage: HookStage,
+        /// The hook command.
+        command: String,
+    },
+
+    /// Output from a hook (stdout or stderr).
+    HookOutput {
+        /// When the hook runs.
+        stage: HookStage,
+        /// The output text.
+        text: String,
+        /// Whether this is from stderr.
+        is_stderr: bool,
+    },
+
+    /// A hook has finished.
+    HookFinished {
+        /// When the hook runs.
+        stage: HookStage,
+        /// The hook command.
+        command: String,
+        /// Exit code, if the command exited normally.
+        exit_code: Option<i32>,
+        /// Whether the hook exited with code 0 within its timeout.
+        success: bool,
+        /// Whether the hook was killed for exceeding its timeout.
+        timed_out: bool,
+        /// Duration in seconds.
+        duration_secs: f64,
+    },
+
+    /// The verify command is running before completion is accepted.
+    VerifyStarted {
+        /// The verify command.
+        command: String,
+    },
+
+    /// The verify command has finished.
+    VerifyFinished {
+        /// The verify command.
+        command: String,
+        /// Exit code, if the command exited normally.
+        exit_code: Option<i32>,
+        /// Whether the command passed; completion is only accepted if it did.
+        success: bool,
+        /// Whether the command was killed for exceeding its timeout.
+        timed_out: bool,
+        /// The last lines of the command's output.
+        output_tail: String,
+        /// Duration in seconds.
+        duration_secs: f64,
+    },
+
     /// The PRD has been updated (e.g., a story marked complete).
     PrdUpdated {
         /// Number of completed stories.
@@ -107,6 +252,12 @@ pub enum Event {
         message: String,
     },
 
+    /// Settings changed through the `RunnerHandle` took effect.
+    ConfigChanged {
+        /// Each setting that changed, with its old and new value.
+        changes: Vec<ConfigChange>,
+    },
+
     /// The runner has completed successfully.
     Completed {
         /// Total iterations run.
@@ -124,19 +275,76 @@ pub enum Event {
     },
 }
 
+impl From<StoryChange> for Event {
+    fn from(change: StoryChange) -> Self {
+        match change {
+            StoryChange::Completed { id, title } => Event::StoryCompleted {
+                story_id: id,
+                story_title: title,
+            },
+            StoryChange::Reopened { id, title } => Event::StoryReopened {
+                story_id: id,
+                story_title: title,
+            },
+            StoryChange::Added { id, title } => Event::StoryAdded {
+                story_id: id,
+                story_title: title,
+            },
+            StoryChange::Removed { id, title } => Event::StoryRemoved {
+                story_id: id,
+                story_title: title,
+            },
+        }
+    }
+}
+
+/// A single setting changed while the runner was running.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    /// Name of the setting (e.g. `max_iterations`).
+    pub setting: String,
+    /// Previous value, formatted for display.
+    pub old: String,
+    /// New value, formatted for display.
+    pub new: String,
+}
+
+impl ConfigChange {
+    /// Create a change record from displayable old and new values.
+    pub fn new(
+        setting: impl Into<String>,
+        old: impl std::fmt::Display,
+        new: impl std::fmt::Display,
+    ) -> Self {
+        Self {
+            setting: setting.into(),
+            old: old.to_string(),
+            new: new.to_string(),
+        }
+    }
+}
+
 /// Reasons for successful completion of the runner.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum CompletionReason {
     /// All stories in the PRD are complete.
     AllStoriesComplete,
     /// The completion phrase was detected in agent output.
     CompletionPhraseDetected,
-    /// Both conditions were met simultaneously.
+    /// A configured completion detector matched the agent output.
+    DetectorMatched {
+        /// Name of the detector that matched.
+        detector: String,
+    },
+    /// All stories are complete and the agent signalled completion
+    /// (by phrase or detector) in the same iteration.
     Both,
 }
 
 /// Reasons for the runner stopping without successful completion.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum StopReason {
     /// Maximum iterations reached.
     MaxIterations,
@@ -152,26 +360,182 @@ pub enum StopReason {
         /// Number of consecutive failures that triggered the circuit breaker.
         consecutive_failures: u32,
     },
+    /// The agent made no progress for too many iterations in a row.
+    Stalled {
+        /// Consecutive iterations without progress.
+        iterations_without_progress: u32,
+    },
+    /// The agent kept hitting its usage limit after waiting it out.
+    UsageLimitPersisted {
+        /// Consecutive usage limit waits before giving up.
+        waits: u32,
+    },
+    /// The agent reported that it cannot continue (`<promise>BLOCKED: ...</promise>`).
+    AgentBlocked {
+        /// Why the agent is blocked.
+        reason: String,
+    },
+    /// The agent needs an answer before it can continue
+    /// (`<promise>NEEDS_INPUT: ...</promise>`).
+    AwaitingInput {
+        /// The agent's question.
+        question: String,
+    },
+}
+
+/// An event together with when and where in the run it happened.
+///
+/// Serializes with the event nested, since some events have fields named
+/// like the envelope's: `{"seq": 4, "timestamp": "...", "run_id": "...",
+/// "iteration": 1, "attempt": 1, "event": {"type": "agent_output", ...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    /// Position of the event in the run, starting at 1 and increasing by
+    /// one for each event sent on the channel.
+    pub seq: u64,
+    /// When the event was sent.
+    pub timestamp: DateTime<Utc>,
+    /// Id of the run, shared by every event from the same runner and kept
+    /// when the run is resumed from a checkpoint.
+    pub run_id: Uuid,
+    /// The iteration the runner was on (0 before the first one starts).
+    pub iteration: u32,
+    /// The agent attempt within the iteration, starting at 1 and increasing
+    /// with each retry (0 before the first iteration starts).
+    pub attempt: u32,
+    /// The event itself.
+    pub event: Event,
 }
 
 /// Sender for events.
-pub type EventSender = mpsc::Sender<Event>;
+///
+/// Wraps each event in an `EventEnvelope` as it is sent. Clones share the
+/// run id, sequence counter and iteration/attempt position.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    /// The underlying channel.
+    tx: mpsc::Sender<EventEnvelope>,
+    /// Envelope state shared between clones.
+    shared: Arc<EnvelopeState>,
+}
+
+/// Envelope fields shared by all clones of an `EventSender`.
+#[derive(Debug)]
+struct EnvelopeState {
+    run_id: Mutex<Uuid>,
+    /// The last sequence number used; held while an envelope is queued so
+    /// the receiver sees sequence numbers in order.
+    seq: Mutex<u64>,
+    iteration: AtomicU32,
+    attempt: AtomicU32,
+}
+
+impl EventSender {
+    /// Send an event, waiting for space in the channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns the event if the receiver has been dropped.
+    pub async fn send(&self, event: Event) -> Result<(), SendError<Event>> {
+        match self.tx.reserve().await {
+            Ok(permit) => {
+                self.stamp(permit, event);
+                Ok(())
+            }
+            Err(_) => Err(SendError(event)),
+        }
+    }
+
+    /// Send an event if there is space in the channel, without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns the event if the channel is full or the receiver has been dropped.
+    pub fn try_send(&self, event: Event) -> Result<(), TrySendError<Event>> {
+        match self.tx.try_reserve() {
+            Ok(permit) => {
+                self.stamp(permit, event);
+                Ok(())
+            }
+            Err(TrySendError::Full(())) => Err(TrySendError::Full(event)),
+            Err(TrySendError::Closed(())) => Err(TrySendError::Closed(event)),
+        }
+    }
+
+    /// The id stamped on every event sent through this channel.
+    pub fn run_id(&self) -> Uuid {
+        *self
+            .shared
+            .run_id
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stamp later events with another run id, such as a resumed run's.
+    pub(crate) fn set_run_id(&self, run_id: Uuid) {
+        *self
+            .shared
+            .run_id
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = run_id;
+    }
+
+    /// Stamp later events with a new iteration, starting again at attempt 1.
+    pub fn set_iteration(&self, iteration: u32) {
+        self.shared.iteration.store(iteration, Ordering::Relaxed);
+        self.shared.attempt.store(1, Ordering::Relaxed);
+    }
+
+    /// Stamp later events with a new attempt within the current iteration.
+    pub fn set_attempt(&self, attempt: u32) {
+        self.shared.attempt.store(attempt, Ordering::Relaxed);
+    }
+
+    /// Wrap an event in an envelope with the next sequence number and queue it.
+    ///
+    /// The envelope is queued while the sequence lock is held, so concurrent
+    /// senders can't deliver sequence numbers out of order.
+    fn stamp(&self, permit: mpsc::Permit<'_, EventEnvelope>, event: Event) {
+        let mut seq = self
+            .shared
+            .seq
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *seq += 1;
+        permit.send(EventEnvelope {
+            seq: *seq,
+            timestamp: Utc::now(),
+            run_id: self.run_id(),
+            iteration: self.shared.iteration.load(Ordering::Relaxed),
+            attempt: self.shared.attempt.load(Ordering::Relaxed),
+            event,
+        });
+    }
+}
 
 /// Receiver for events.
-pub type EventReceiver = mpsc::Receiver<Event>;
+pub type EventReceiver = mpsc::Receiver<EventEnvelope>;
 
 /// Create a new event channel with the default buffer size.
 ///
-/// Returns a sender and receiver pair for event communication.
+/// Returns a sender and receiver pair for event communication. Events sent
+/// on the channel are stamped with a new random run id.
 pub fn channel() -> (EventSender, EventReceiver) {
-    mpsc::channel(DEFAULT_CHANNEL_SIZE)
+    channel_with_size(DEFAULT_CHANNEL_SIZE)
 }
 
 /// Create a new event channel with a custom buffer size.
 ///
 /// Returns a sender and receiver pair for event communication.
 pub fn channel_with_size(size: usize) -> (EventSender, EventReceiver) {
-    mpsc::channel(size)
+    let (tx, rx) = mpsc::channel(size);
+    let shared = Arc::new(EnvelopeState {
+        run_id: Mutex::new(Uuid::new_v4()),
+        seq: Mutex::new(0),
+        iteration: AtomicU32::new(0),
+        attempt: AtomicU32::new(0),
+    });
+    (EventSender { tx, shared }, rx)
 }
 
 impl Event {
@@ -218,6 +582,9 @@ impl std::fmt::Display for CompletionReason {
         match self {
             CompletionReason::AllStoriesComplete => write!(f, "all stories complete"),
             CompletionReason::CompletionPhraseDetected => write!(f, "completion phrase detected"),
+            CompletionReason::DetectorMatched { detector } => {
+                write!(f, "completion detector matched: {}", detector)
+            }
             CompletionReason::Both => {
                 write!(f, "all stories complete and completion phrase detected")
             }
@@ -238,6 +605,20 @@ impl std::fmt::Display for StopReason {
                 "circuit breaker triggered after {} consecutive failures",
                 consecutive_failures
             ),
+            StopReason::Stalled {
+                iterations_without_progress,
+            } => write!(
+                f,
+                "stalled after {} iterations without progress",
+                iterations_without_progress
+            ),
+            StopReason::UsageLimitPersisted { waits } => {
+                write!(f, "usage limit still reached after {} waits", waits)
+            }
+            StopReason::AgentBlocked { reason } => write!(f, "agent blocked: {}", reason),
+            StopReason::AwaitingInput { question } => {
+                write!(f, "agent needs input: {}", question)
+            }
         }
     }
 }
@@ -281,6 +662,13 @@ mod tests {
             CompletionReason::CompletionPhraseDetected.to_string(),
             "completion phrase detected"
         );
+        assert_eq!(
+            CompletionReason::DetectorMatched {
+                detector: "regex `^done$`".to_string()
+            }
+            .to_string(),
+            "completion detector matched: regex `^done$`"
+        );
         assert_eq!(
             CompletionReason::Both.to_string(),
             "all stories complete and completion phrase detected"
@@ -308,5 +696,167 @@ mod tests {
             .to_string(),
             "circuit breaker triggered after 5 consecutive failures"
         );
+        assert_eq!(
+            StopReason::Stalled {
+                iterations_without_progress: 3
+            }
+            .to_string(),
+            "stalled after 3 iterations without progress"
+        );
+        assert_eq!(
+            StopReason::AgentBlocked {
+                reason: "missing AWS credentials".to_string()
+            }
+            .to_string(),
+            "agent blocked: missing AWS credentials"
+        );
+        assert_eq!(
+            StopReason::AwaitingInput {
+                question: "which region?".to_string()
+            }
+            .to_string(),
+            "agent needs input: which region?"
+        );
+        assert_eq!(
+            StopReason::UsageLimitPersisted { waits: 6 }.to_string(),
+            "usage limit still reached after 6 waits"
+        );
+    }
+
+    #[test]
+    fn test_event_json_schema() {
+        let event = Event::IterationStarted {
+            iteration: 2,
+            max_iterations: 20,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"type": "iteration_started", "iteration": 2, "max_iterations": 20})
+        );
+
+        let event = Event::Stopped {
+            iterations: 4,
+            reason: StopReason::Stalled {
+                iterations_without_progress: 3,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "type": "stopped",
+                "iterations": 4,
+                "reason": {"type": "stalled", "iterations_without_progress": 3}
+            })
+        );
+
+        let event = Event::HookStarted {
+            stage: HookStage::PreIteration,
+            command: "git pull".to_string(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""stage":"pre_iteration""#));
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_reason_json_roundtrip() {
+        let reasons = [
+            CompletionReason::AllStoriesComplete,
+            CompletionReason::DetectorMatched {
+                detector: "regex `^done$`".to_string(),
+            },
+        ];
+        for reason in reasons {
+            let json = serde_json::to_string(&reason).unwrap();
+            assert_eq!(
+                serde_json::from_str::<CompletionReason>(&json).unwrap(),
+                reason
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&CompletionReason::Both).unwrap(),
+            r#"{"type":"both"}"#
+        );
+
+        let reason = StopReason::AwaitingInput {
+            question: "which region?".to_string(),
+        };
+        let json = serde_json::to_string(&reason).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"awaiting_input","question":"which region?"}"#
+        );
+        assert_eq!(serde_json::from_str::<StopReason>(&json).unwrap(), reason);
+    }
+
+    #[tokio::test]
+    async fn test_sender_stamps_envelopes() {
+        let (tx, mut rx) = channel();
+        let other = tx.clone();
+
+        tx.send(Event::progress("setup")).await.unwrap();
+        tx.set_iteration(3);
+        other.send(Event::progress("first try")).await.unwrap();
+        other.set_attempt(2);
+        tx.try_send(Event::progress("retry")).unwrap();
+        drop((tx, other));
+
+        let mut envelopes = Vec::new();
+        while let Some(envelope) = rx.recv().await {
+            envelopes.push(envelope);
+        }
+        let stamps: Vec<_> = envelopes
+            .iter()
+            .map(|e| (e.seq, e.iteration, e.attempt))
+            .collect();
+        assert_eq!(stamps, [(1, 0, 0), (2, 3, 1), (3, 3, 2)]);
+        assert!(envelopes.iter().all(|e| e.run_id == envelopes[0].run_id));
+
+        // Each channel is a separate run
+        let (tx2, _rx2) = channel();
+        assert_ne!(tx2.run_id(), envelopes[0].run_id);
+    }
+
+    #[test]
+    fn test_send_to_closed_channel_returns_event() {
+        let (tx, rx) = channel_with_size(1);
+        tx.try_send(Event::progress("fills the buffer")).unwrap();
+        assert!(matches!(
+            tx.try_send(Event::progress("no room")),
+            Err(TrySendError::Full(Event::Progress { .. }))
+        ));
+        drop(rx);
+        assert!(matches!(
+            tx.try_send(Event::progress("nobody listening")),
+            Err(TrySendError::Closed(Event::Progress { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_envelope_json_roundtrip() {
+        let envelope = EventEnvelope {
+            seq: 7,
+            timestamp: "2024-01-27T12:00:00Z".parse().unwrap(),
+            run_id: Uuid::nil(),
+            iteration: 2,
+            attempt: 1,
+            event: Event::agent_output("hello"),
+        };
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "seq": 7,
+                "timestamp": "2024-01-27T12:00:00Z",
+                "run_id": "00000000-0000-0000-0000-000000000000",
+                "iteration": 2,
+                "attempt": 1,
+                "event": {"type": "agent_output", "text": "hello", "is_stderr": false}
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<EventEnvelope>(json).unwrap(),
+            envelope
+        );
     }
 }
@@ -0,0 +1,151 @@
+//! Feedback about the previous iteration, added to the next prompt.
+//!
+//! This module provides `IterationFeedback`, which the runner records after
+//! each agent run when `Config::iteration_feedback` is enabled. Rendered into
+//! the next prompt, it tells the agent how its last attempt ended (exit code,
+//! detected errors, retries, a failed verify command and the tail of its
+//! output) so it can course-correct instead of starting blind.
+
+/// Marker appended when the section had to be cut to fit the size cap.
+const TRUNCATED_MARKER: &str = "\n[feedback truncated]";
+
+/// What happened in the previous iteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IterationFeedback {
+    /// The iteration number.
+    pub(crate) iteration: u32,
+    /// The agent's exit code (`None` if it was killed or never finished).
+    pub(crate) exit_code: Option<i32>,
+    /// The last agent error, such as a timeout or detected error pattern.
+    pub(crate) error: Option<String>,
+    /// Retries used after errors or timeouts.
+    pub(crate) retries: u32,
+    /// The last lines of the agent's output.
+    pub(crate) last_lines: Vec<String>,
+}
+
+impl IterationFeedback {
+    /// Render the feedback section under `header`.
+    ///
+    /// `verify` is the failed verify command section, if any. When the
+    /// result is longer than `max_chars` (0 = unlimited), output lines are
+    /// dropped from the front first, then the section is cut off.
+    pub(crate) fn render(&self, header: &str, verify: Option<&str>, max_chars: usize) -> String {
+        let mut skipped = 0;
+        loop {
+            let section = self.format(header, verify, skipped);
+            if max_chars == 0 || section.chars().count() <= max_chars {
+                return section;
+            }
+            if skipped < self.last_lines.len() {
+                skipped += 1;
+                continue;
+            }
+            return truncate(&section, max_chars);
+        }
+    }
+
+    /// Format the section, leaving out the first `skipped` output lines.
+    fn format(&self, header: &str, verify: Option<&str>, skipped: usize) -> String {
+        let exit_code = match self.exit_code {
+            Some(code) => code.to_string(),
+            None => "none (the agent was killed or did not finish)".to_string(),
+        };
+        let mut section = format!(
+            "{}\n\n- Iteration: {}\n- Exit code: {}\n",
+            header, self.iteration, exit_code
+        );
+        if let Some(error) = &self.error {
+            section.push_str(&format!("- Error: {}\n", error));
+        }
+        section.push_str(&format!("- Retries: {}\n", self.retries));
+
+        let lines = &self.last_lines[skipped..];
+        if !lines.is_empty() {
+            section.push_str(&format!(
+                "\nLast {} line{} of output:\n\n```\n{}\n```\n",
+                lines.len(),
+                if lines.len() == 1 { "" } else { "s" },
+                lines.join("\n")
+            ));
+        }
+        if let Some(verify) = verify {
+            section.push('\n');
+            section.push_str(verify);
+            section.push('\n');
+        }
+        section.trim_end().to_string()
+    }
+}
+
+/// Cut `text` to at most `max_chars` characters, ending with a marker.
+fn truncate(text: &str, max_chars: usize) -> String {
+    let keep = max_chars.saturating_sub(TRUNCATED_MARKER.chars().count());
+    let mut truncated: String = text.chars().take(keep).collect();
+    truncated.push_str(TRUNCATED_MARKER);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback() -> IterationFeedback {
+        IterationFeedback {
+            iteration: 3,
+            exit_code: None,
+            error: Some("agent timed out after 900 seconds".to_string()),
+            retries: 2,
+            last_lines: vec!["compiling".to_string(), "running tests".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            feedback().render("## Previous iteration", None, 0),
+            "## Previous iteration\n\n\
+             - Iteration: 3\n\
+             - Exit code: none (the agent was killed or did not finish)\n\
+             - Error: agent timed out after 900 seconds\n\
+             - Retries: 2\n\n\
+             Last 2 lines of output:\n\n\
+             ```\ncompiling\nrunning tests\n```"
+        );
+    }
+
+    #[test]
+    fn test_render_with_verify_output() {
+        let feedback = IterationFeedback {
+            exit_code: Some(0),
+            error: None,
+            retries: 0,
+            last_lines: Vec::new(),
+            ..feedback()
+        };
+        assert_eq!(
+            feedback.render("# Last time", Some("## Verification failed\n\nerror"), 0),
+            "# Last time\n\n\
+             - Iteration: 3\n\
+             - Exit code: 0\n\
+             - Retries: 0\n\n\
+             ## Verification failed\n\nerror"
+        );
+    }
+
+    #[test]
+    fn test_render_drops_output_lines_to_fit() {
+        let full = feedback().render("## Previous iteration", None, 0);
+        let capped = feedback().render("## Previous iteration", None, full.len() - 1);
+        assert!(capped.contains("Last 1 line of output:\n\n```\nrunning tests\n```"));
+        assert!(!capped.contains("compiling"));
+    }
+
+    #[test]
+    fn test_render_truncates_when_lines_are_not_enough() {
+        let capped = feedback().render("## Previous iteration", None, 60);
+        assert_eq!(capped.chars().count(), 60);
+        assert!(capped.starts_with("## Previous iteration\n\n- Iteration: 3"));
+        assert!(capped.ends_with(TRUNCATED_MARKER));
+    }
+}
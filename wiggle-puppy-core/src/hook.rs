@@ -0,0 +1,280 @@
+//! Commands run before and after each agent invocation.
+//!
+//! This module provides `Hook`, a shell command with its own timeout and a
+//! `HookFailure` policy. Pre-iteration hooks run before the agent (e.g.
+//! `git pull`); post-iteration hooks run after it (e.g. formatting, linting
+//! or snapshotting artifacts). Hooks run with `sh -c` and receive iteration
+//! metadata through environment variables:
+//!
+//! - `WIGGLE_PUPPY_RUN_ID`: the run identifier
+//! - `WIGGLE_PUPPY_HOOK_STAGE`: `pre-iteration` or `post-iteration`
+//! - `WIGGLE_PUPPY_ITERATION`: the current iteration (1-indexed)
+//! - `WIGGLE_PUPPY_MAX_ITERATIONS`: the iteration limit
+//! - `WIGGLE_PUPPY_PRD_PATH`: the PRD path, if one is configured
+//! - `WIGGLE_PUPPY_NEXT_STORY_ID` / `WIGGLE_PUPPY_NEXT_STORY_TITLE`: the
+//!   next story in the PRD, if any
+//! - `WIGGLE_PUPPY_EXIT_CODE`: the agent's exit code (post-iteration only;
+//!   empty if it was killed)
+
+use crate::error::Result;
+use crate::event::{Event, EventSender};
+use crate::process::{run_shell, ShellOutput};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Default hook timeout in seconds (5 minutes).
+pub const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 300;
+
+/// When a hook runs relative to the agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    /// Before the agent runs.
+    PreIteration,
+    /// After the agent has run.
+    PostIteration,
+}
+
+impl std::fmt::Display for HookStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookStage::PreIteration => write!(f, "pre-iteration"),
+            HookStage::PostIteration => write!(f, "post-iteration"),
+        }
+    }
+}
+
+/// How the runner reacts when a hook fails or times out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HookFailure {
+    /// Carry on silently.
+    Ignore,
+    /// Emit a warning and carry on.
+    #[default]
+    Warn,
+    /// Run the iteration again after a backoff, up to `max_retries` times;
+    /// once retries run out the failure is treated as a warning.
+    RetryIteration,
+    /// Stop the run.
+    Stop,
+}
+
+impl std::fmt::Display for HookFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookFailure::Ignore => write!(f, "ignore"),
+            HookFailure::Warn => write!(f, "warn"),
+            HookFailure::RetryIteration => write!(f, "retry"),
+            HookFailure::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+impl std::str::FromStr for HookFailure {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(HookFailure::Ignore),
+            "warn" => Ok(HookFailure::Warn),
+            "retry" | "retry-iteration" => Ok(HookFailure::RetryIteration),
+            "stop" => Ok(HookFailure::Stop),
+            other => Err(format!(
+                "unknown hook failure policy '{}' (expected ignore, warn, retry or stop)",
+                other
+            )),
+        }
+    }
+}
+
+/// A shell command run before or after each agent invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    /// The command, run with `sh -c`.
+    pub command: String,
+    /// Timeout in seconds (0 = no timeout).
+    pub timeout_secs: u64,
+    /// What to do when the command fails or times out.
+    pub on_failure: HookFailure,
+}
+
+impl Hook {
+    /// Create a hook with the default timeout that warns on failure.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wiggle_puppy_core::{Hook, HookFailure};
+    ///
+    /// let hook = Hook::new("cargo fmt")
+    ///     .timeout_secs(60)
+    ///     .on_failure(HookFailure::Stop);
+    /// assert_eq!(hook.command, "cargo fmt");
+    /// ```
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            timeout_secs: DEFAULT_HOOK_TIMEOUT_SECS,
+            on_failure: HookFailure::default(),
+        }
+    }
+
+    /// Set the timeout in seconds (0 to disable).
+    pub fn timeout_secs(mut self, secs: u64) -> Self {
+        self.timeout_secs = secs;
+        self
+    }
+
+    /// Set the failure policy.
+    pub fn on_failure(mut self, policy: HookFailure) -> Self {
+        self.on_failure = policy;
+        self
+    }
+
+    /// Run the hook, streaming its output as `Event::HookOutput`.
+    ///
+    /// Emits `Event::HookStarted` before and `Event::HookFinished` after.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started or `cancel`
+    /// fired while it ran.
+    pub(crate) async fn run(
+        &self,
+        stage: HookStage,
+        env: &[(String, String)],
+        cancel: &CancellationToken,
+        events: &EventSender,
+    ) -> Result<ShellOutput> {
+        let _ = events
+            .send(Event::HookStarted {
+                stage,
+                command: self.command.clone(),
+            })
+            .await;
+
+        let timeout = (self.timeout_secs > 0).then(|| Duration::from_secs(self.timeout_secs));
+        let output = run_shell(
+            &self.command,
+            env,
+            timeout,
+            cancel,
+            events,
+            |line, is_stderr| {
+                Some(Event::HookOutput {
+                    stage,
+                    text: line.to_string(),
+                    is_stderr,
+                })
+            },
+        )
+        .await?;
+
+        let _ = events
+            .send(Event::HookFinished {
+                stage,
+                command: self.command.clone(),
+                exit_code: output.exit_code,
+                success: output.success(),
+                timed_out: output.timed_out,
+                duration_secs: output.duration_secs,
+            })
+            .await;
+
+        Ok(output)
+    }
+
+    /// Describe why the hook failed, for warnings and stop reasons.
+    pub(crate) fn failure_message(&self, stage: HookStage, output: &ShellOutput) -> String {
+        let result = if output.timed_out {
+            format!("timed out after {} seconds", self.timeout_secs)
+        } else {
+            match output.exit_code {
+                Some(code) => format!("failed with exit code {}", code),
+                None => "was killed by a signal".to_string(),
+            }
+        };
+        format!("{} hook '{}' {}", stage, self.command, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{channel, EventEnvelope};
+
+    #[test]
+    fn test_hook_builder() {
+        let hook = Hook::new("git pull");
+        assert_eq!(hook.timeout_secs, DEFAULT_HOOK_TIMEOUT_SECS);
+        assert_eq!(hook.on_failure, HookFailure::Warn);
+
+        let hook = hook
+            .timeout_secs(10)
+            .on_failure(HookFailure::RetryIteration);
+        assert_eq!(hook.timeout_secs, 10);
+        assert_eq!(hook.on_failure, HookFailure::RetryIteration);
+    }
+
+    #[test]
+    fn test_hook_failure_parse_roundtrip() {
+        for policy in [
+            HookFailure::Ignore,
+            HookFailure::Warn,
+            HookFailure::RetryIteration,
+            HookFailure::Stop,
+        ] {
+            assert_eq!(policy.to_string().parse::<HookFailure>(), Ok(policy));
+        }
+        assert!("explode".parse::<HookFailure>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hook_run_emits_events() {
+        let (tx, mut rx) = channel();
+        let env = vec![("WIGGLE_PUPPY_ITERATION".to_string(), "4".to_string())];
+        let hook = Hook::new("echo \"iteration $WIGGLE_PUPPY_ITERATION\"; exit 2");
+
+        let output = hook
+            .run(
+                HookStage::PostIteration,
+                &env,
+                &CancellationToken::new(),
+                &tx,
+            )
+            .await
+            .expect("hook should run");
+        assert_eq!(output.exit_code, Some(2));
+        assert_eq!(
+            hook.failure_message(HookStage::PostIteration, &output),
+            "post-iteration hook 'echo \"iteration $WIGGLE_PUPPY_ITERATION\"; exit 2' \
+             failed with exit code 2"
+        );
+
+        drop(tx);
+        let mut events = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            events.push(event);
+        }
+        assert!(matches!(
+            events.first(),
+            Some(Event::HookStarted {
+                stage: HookStage::PostIteration,
+                ..
+            })
+        ));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::HookOutput { text, is_stderr: false, .. } if text == "iteration 4"
+        )));
+        assert!(matches!(
+            events.last(),
+            Some(Event::HookFinished {
+                exit_code: Some(2),
+                success: false,
+                ..
+            })
+        ));
+    }
+}
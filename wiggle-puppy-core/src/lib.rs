@@ -5,15 +5,37 @@
 //! configuration, agent execution, and the main runner loop.
 
 pub mod agent;
+pub mod checkpoint;
+pub mod completion;
 pub mod config;
 pub mod error;
 pub mod event;
+mod feedback;
+pub mod hook;
+pub mod pattern;
 pub mod prd;
+mod process;
+pub mod progress;
 pub mod runner;
+mod stall;
+pub mod template;
 
-pub use agent::{Agent, AgentOutput};
-pub use config::Config;
+pub use agent::{Agent, AgentOutput, PromptDelivery};
+pub use checkpoint::Checkpoint;
+pub use completion::{
+    CompletionDetector, CompletionPolicy, ConsecutiveDetector, JsonSentinelDetector,
+    LastLinesDetector, RegexDetector, SentinelLineDetector, SubstringDetector,
+};
+pub use config::{Config, PromptSource};
 pub use error::{Error, Result};
-pub use event::{channel, CompletionReason, Event, EventReceiver, EventSender, StopReason};
-pub use prd::{Prd, Story, StoryStatus};
-pub use runner::{Outcome, Runner, RunnerHandle};
+pub use event::{
+    channel, CompletionReason, ConfigChange, Event, EventEnvelope, EventReceiver, EventSender,
+    StopReason, EVENT_SCHEMA_VERSION,
+};
+pub use hook::{Hook, HookFailure, HookStage};
+pub use pattern::{ErrorClass, ErrorPattern};
+pub use prd::{Prd, Story, StoryChange, StoryStatus};
+pub use progress::{ProgressEntry, ProgressLog, StoryRef};
+pub use runner::{Outcome, Runner, RunnerHandle, RunnerState};
+pub use template::TemplateVars;
+pub use uuid::Uuid;
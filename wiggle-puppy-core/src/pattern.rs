@@ -0,0 +1,218 @@
+//! Classified error patterns for agent output.
+//!
+//! This module provides `ErrorPattern`, a regular expression tagged with an
+//! `ErrorClass` that tells the runner how to react when the pattern matches
+//! a line of agent output: retry, wait out a rate limit, stop, or ignore.
+
+use crate::error::{Error, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// How the runner reacts when an error pattern matches agent output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// A transient failure; retry with exponential backoff.
+    Transient,
+    /// The agent is being rate limited; wait longer before retrying.
+    RateLimited,
+    /// Authentication or other unrecoverable failure; stop the run.
+    Fatal,
+    /// Known-benign output; the line is not checked against any other pattern.
+    Ignore,
+}
+
+/// A regular expression matched against each line of agent output.
+///
+/// A line matching any `Ignore` pattern is exempt from the others, so
+/// user-supplied exemptions can override the default patterns. Otherwise
+/// patterns are checked in order and the first match wins.
+#[derive(Debug, Clone)]
+pub struct ErrorPattern {
+    /// The compiled regular expression.
+    regex: Regex,
+    /// How to react when the pattern matches.
+    class: ErrorClass,
+}
+
+impl ErrorPattern {
+    /// Create a pattern from a regular expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidErrorPattern` if the regex does not compile.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wiggle_puppy_core::{ErrorClass, ErrorPattern};
+    ///
+    /// let pattern = ErrorPattern::new(r"API Error: 5\d\d", ErrorClass::Transient).unwrap();
+    /// assert!(pattern.is_match("API Error: 503 Service Unavailable"));
+    /// ```
+    pub fn new(pattern: &str, class: ErrorClass) -> Result<Self> {
+        let regex = Regex::new(pattern).map_err(|source| Error::InvalidErrorPattern {
+            pattern: pattern.to_string(),
+            source,
+        })?;
+        Ok(Self { regex, class })
+    }
+
+    /// Create a pattern that matches the given text literally.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use wiggle_puppy_core::{ErrorClass, ErrorPattern};
+    ///
+    /// let pattern = ErrorPattern::literal("FATAL (core)", ErrorClass::Fatal);
+    /// assert!(pattern.is_match("FATAL (core) dumped"));
+    /// ```
+    pub fn literal(text: &str, class: ErrorClass) -> Self {
+        let regex = Regex::new(&regex::escape(text)).expect("escaped literal is a valid regex");
+        Self { regex, class }
+    }
+
+    /// Get the regular expression source.
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    /// Get the class of this pattern.
+    pub fn class(&self) -> ErrorClass {
+        self.class
+    }
+
+    /// Check if the pattern matches the given line.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+impl PartialEq for ErrorPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str() && self.class == other.class
+    }
+}
+
+impl std::fmt::Display for ErrorPattern {
+    /// Formats as `class:regex`, the form accepted by `FromStr`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.class, self.as_str())
+    }
+}
+
+impl std::fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorClass::Transient => write!(f, "transient"),
+            ErrorClass::RateLimited => write!(f, "rate-limited"),
+            ErrorClass::Fatal => write!(f, "fatal"),
+            ErrorClass::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "transient" => Ok(ErrorClass::Transient),
+            "rate-limited" => Ok(ErrorClass::RateLimited),
+            "fatal" | "auth" => Ok(ErrorClass::Fatal),
+            "ignore" => Ok(ErrorClass::Ignore),
+            other => Err(format!(
+                "unknown error class '{}' (expected transient, rate-limited, fatal or ignore)",
+                other
+            )),
+        }
+    }
+}
+
+/// Parse `[class:]regex`, defaulting to `ErrorClass::Transient`.
+///
+/// The prefix is only treated as a class when it names one, so regexes
+/// containing a colon (like `Error: .*`) parse as-is.
+impl std::str::FromStr for ErrorPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (class, pattern) = match s.split_once(':') {
+            Some((prefix, rest)) => match prefix.parse::<ErrorClass>() {
+                Ok(class) => (class, rest),
+                Err(_) => (ErrorClass::Transient, s),
+            },
+            None => (ErrorClass::Transient, s),
+        };
+        ErrorPattern::new(pattern, class).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_pattern_matches() {
+        let pattern = ErrorPattern::new(r"(?i)rate.?limit", ErrorClass::RateLimited).unwrap();
+        assert!(pattern.is_match("Rate limit exceeded"));
+        assert!(pattern.is_match("hit the rate_limit"));
+        assert!(!pattern.is_match("all good"));
+        assert_eq!(pattern.class(), ErrorClass::RateLimited);
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let result = ErrorPattern::new("(unclosed", ErrorClass::Transient);
+        assert!(matches!(result, Err(Error::InvalidErrorPattern { .. })));
+    }
+
+    #[test]
+    fn test_literal_escapes_metacharacters() {
+        let pattern = ErrorPattern::literal("a.b*", ErrorClass::Transient);
+        assert!(pattern.is_match("x a.b* y"));
+        assert!(!pattern.is_match("aXbbb"));
+    }
+
+    #[test]
+    fn test_parse_with_class_prefix() {
+        let pattern: ErrorPattern = "fatal:Invalid API key".parse().unwrap();
+        assert_eq!(pattern.class(), ErrorClass::Fatal);
+        assert_eq!(pattern.as_str(), "Invalid API key");
+
+        let pattern: ErrorPattern = "rate-limited:429".parse().unwrap();
+        assert_eq!(pattern.class(), ErrorClass::RateLimited);
+    }
+
+    #[test]
+    fn test_parse_without_class_prefix() {
+        let pattern: ErrorPattern = "Error: No messages".parse().unwrap();
+        assert_eq!(pattern.class(), ErrorClass::Transient);
+        assert_eq!(pattern.as_str(), "Error: No messages");
+
+        assert!("fatal:(unclosed".parse::<ErrorPattern>().is_err());
+    }
+
+    #[test]
+    fn test_error_pattern_display_roundtrip() {
+        let pattern = ErrorPattern::new(r"API Error: 5\d\d", ErrorClass::RateLimited).unwrap();
+        assert_eq!(pattern.to_string(), r"rate-limited:API Error: 5\d\d");
+        assert_eq!(
+            pattern.to_string().parse::<ErrorPattern>().unwrap(),
+            pattern
+        );
+    }
+
+    #[test]
+    fn test_error_class_display_roundtrip() {
+        for class in [
+            ErrorClass::Transient,
+            ErrorClass::RateLimited,
+            ErrorClass::Fatal,
+            ErrorClass::Ignore,
+        ] {
+            assert_eq!(class.to_string().parse::<ErrorClass>(), Ok(class));
+        }
+    }
+}
@@ -67,6 +67,42 @@ pub enum StoryStatus {
     InProgress,
 }
 
+/// A change to a single story between two versions of a PRD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoryChange {
+    /// The story is now marked as passing.
+    Completed {
+        /// The story ID.
+        id: String,
+        /// The story title.
+        title: String,
+    },
+
+    /// The story was passing and no longer is.
+    Reopened {
+        /// The story ID.
+        id: String,
+        /// The story title.
+        title: String,
+    },
+
+    /// The story is new.
+    Added {
+        /// The story ID.
+        id: String,
+        /// The story title.
+        title: String,
+    },
+
+    /// The story no longer exists.
+    Removed {
+        /// The story ID.
+        id: String,
+        /// The story title.
+        title: String,
+    },
+}
+
 impl Prd {
     /// Load a PRD from a JSON file.
     ///
@@ -135,6 +171,40 @@ impl Prd {
             .min_by_key(|s| s.priority)
     }
 
+    /// Compare this PRD with a later version of it, story by story.
+    ///
+    /// Stories are matched by ID. Changes for stories in `after` come first,
+    /// in its order, followed by stories that were removed.
+    pub fn diff(&self, after: &Prd) -> Vec<StoryChange> {
+        let mut changes = Vec::new();
+
+        for story in &after.stories {
+            let id = story.id.clone();
+            let title = story.title.clone();
+            match self.get_story(&story.id) {
+                None => changes.push(StoryChange::Added { id, title }),
+                Some(old) if !old.passes && story.passes => {
+                    changes.push(StoryChange::Completed { id, title })
+                }
+                Some(old) if old.passes && !story.passes => {
+                    changes.push(StoryChange::Reopened { id, title })
+                }
+                Some(_) => {}
+            }
+        }
+
+        for story in &self.stories {
+            if after.get_story(&story.id).is_none() {
+                changes.push(StoryChange::Removed {
+                    id: story.id.clone(),
+                    title: story.title.clone(),
+                });
+            }
+        }
+
+        changes
+    }
+
     /// Get a mutable reference to a story by its ID.
     pub fn get_story_mut(&mut self, id: &str) -> Option<&mut Story> {
         self.stories.iter_mut().find(|s| s.id == id)
@@ -338,6 +408,64 @@ mod tests {
         assert!(prd.stories[1].passes);
     }
 
+    #[test]
+    fn test_diff_unchanged() {
+        let prd = create_test_prd();
+        assert!(prd.diff(&prd.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_completed_and_reopened() {
+        let before = create_test_prd();
+        let mut after = before.clone();
+        after.stories[0].passes = false;
+        after.stories[1].passes = true;
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                StoryChange::Reopened {
+                    id: "1".to_string(),
+                    title: "First story".to_string(),
+                },
+                StoryChange::Completed {
+                    id: "2".to_string(),
+                    title: "Second story".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_added_and_removed() {
+        let before = create_test_prd();
+        let mut after = before.clone();
+        after.stories.remove(2);
+        after.stories.push(Story {
+            id: "5".to_string(),
+            title: "Fifth story".to_string(),
+            description: "Do the fifth thing".to_string(),
+            priority: 5,
+            passes: false,
+            acceptance_criteria: vec![],
+            depends_on: vec![],
+        });
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                StoryChange::Added {
+                    id: "5".to_string(),
+                    title: "Fifth story".to_string(),
+                },
+                StoryChange::Removed {
+                    id: "3".to_string(),
+                    title: "Third story".to_string(),
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_load_and_save_roundtrip() {
         let prd = create_test_prd();
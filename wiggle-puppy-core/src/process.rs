@@ -0,0 +1,380 @@
+//! Child process helpers shared by the agent, verify command and hooks.
+//!
+//! This module provides process group handling, so a kill reaches every
+//! process a command spawned, and `run_shell` for running `sh -c` commands
+//! with a timeout while streaming their output.
+
+use crate::error::{Error, Result};
+use crate::event::{Event, EventSender};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+/// The process group a child runs in, killed when dropped.
+///
+/// Agents spawn their own subprocesses (node workers, tool shells), so
+/// signalling only the direct child would leave grandchildren running. The
+/// child is started as the leader of a new group and every kill path signals
+/// the whole group. Dropping the guard while still armed (an early return or
+/// an abandoned future) kills whatever is left.
+#[derive(Debug)]
+pub(crate) struct ProcessGroup {
+    /// Group id (the child's pid); `None` once released or off Unix.
+    pgid: Option<u32>,
+}
+
+impl ProcessGroup {
+    /// Track the group led by a freshly spawned child.
+    pub(crate) fn of(child: &Child) -> Self {
+        Self {
+            pgid: if cfg!(unix) { child.id() } else { None },
+        }
+    }
+
+    /// Send a signal to every process in the group.
+    #[cfg(unix)]
+    pub(crate) fn signal(&self, signal: libc::c_int) {
+        if let Some(pgid) = self.pgid {
+            // SAFETY: killpg(2) has no memory-safety preconditions. The group
+            // id is our child's pid, which stays reserved while the group has
+            // members or the child is unreaped.
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, signal);
+            }
+        }
+    }
+
+    /// Stop tracking the group once the child has been reaped.
+    pub(crate) fn release(&mut self) {
+        self.pgid = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        self.signal(libc::SIGKILL);
+    }
+}
+
+/// Configure a command to run in its own process group and die with its handle.
+///
+/// The child is moved out of the terminal's foreground group, so stdin is set
+/// to `/dev/null` unless the caller pipes it afterwards.
+pub(crate) fn isolate(cmd: &mut Command) {
+    cmd.stdin(Stdio::null());
+    cmd.kill_on_drop(true);
+    #[cfg(unix)]
+    cmd.process_group(0);
+}
+
+/// Kill the child and every process in its group, and reap the child.
+///
+/// The guard is released afterwards: once the child is reaped its pid, and
+/// so the group id, may be reused by an unrelated process.
+pub(crate) async fn kill_tree(child: &mut Child, group: &mut ProcessGroup) {
+    #[cfg(unix)]
+    group.signal(libc::SIGKILL);
+    let _ = child.kill().await;
+    group.release();
+}
+
+/// How often `terminate` checks whether the child has exited.
+#[cfg(unix)]
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Check whether a child has exited, without reaping it.
+///
+/// An exited but unreaped child keeps its pid, and so its group id, reserved,
+/// which lets the group still be signalled safely.
+#[cfg(unix)]
+fn has_exited(pid: u32) -> bool {
+    // SAFETY: waitid(2) only writes to the zeroed siginfo passed in. WNOWAIT
+    // leaves the child waitable, so tokio still reaps it later.
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        let result = libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        );
+        // With WNOHANG, si_signo stays 0 while the child is still running;
+        // an error means there is no such child left to wait for
+        result != 0 || info.si_signo != 0
+    }
+}
+
+/// Ask the child's process group to exit with SIGTERM, then SIGKILL whatever
+/// is still running once the child exits or `grace` runs out.
+///
+/// The child is only reaped after the SIGKILL, so the group id can't have
+/// been reused by then.
+pub(crate) async fn terminate(child: &mut Child, group: &mut ProcessGroup, grace: Duration) {
+    #[cfg(unix)]
+    {
+        group.signal(libc::SIGTERM);
+        // A suspended child can't act on SIGTERM until it is continued
+        group.signal(libc::SIGCONT);
+        if let Some(pid) = child.id() {
+            let deadline = Instant::now() + grace;
+            while !has_exited(pid) && Instant::now() < deadline {
+                tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = grace;
+    kill_tree(child, group).await;
+}
+
+/// Output captured from a shell command.
+#[derive(Debug, Clone)]
+pub(crate) struct ShellOutput {
+    /// Exit code, if the command exited normally.
+    pub exit_code: Option<i32>,
+    /// Combined stdout and stderr lines in the order received.
+    pub lines: Vec<String>,
+    /// Whether the command was killed for exceeding its timeout.
+    pub timed_out: bool,
+    /// Duration of the run in seconds.
+    pub duration_secs: f64,
+}
+
+impl ShellOutput {
+    /// Check if the command exited with code 0 within its timeout.
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.exit_code == Some(0)
+    }
+
+    /// Get the last `n` lines of output, joined with newlines.
+    pub fn tail(&self, n: usize) -> String {
+        let start = self.lines.len().saturating_sub(n);
+        self.lines[start..].join("\n")
+    }
+}
+
+/// Run a command with `sh -c`, streaming its output.
+///
+/// Each stdout/stderr line is passed to `line_event` along with whether it
+/// came from stderr; any event it returns is sent. The command and all its
+/// children are killed when `timeout` elapses or `cancel` fires.
+///
+/// # Errors
+///
+/// Returns `Error::CommandSpawnError` if the shell cannot be started.
+/// Returns `Error::Cancelled` if `cancel` fires while the command runs.
+pub(crate) async fn run_shell(
+    command: &str,
+    env: &[(String, String)],
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+    events: &EventSender,
+    mut line_event: impl FnMut(&str, bool) -> Option<Event>,
+) -> Result<ShellOutput> {
+    let start = Instant::now();
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd.envs(env.iter().map(|(k, v)| (k, v)));
+    isolate(&mut cmd);
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let mut child = cmd.spawn().map_err(|source| Error::CommandSpawnError {
+        command: command.to_string(),
+        source,
+    })?;
+    let mut group = ProcessGroup::of(&child);
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| Error::other("failed to capture stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| Error::other("failed to capture stderr"))?;
+    let mut stdout_reader = BufReader::new(stdout).lines();
+    let mut stderr_reader = BufReader::new(stderr).lines();
+    let mut stdout_done = false;
+    let mut stderr_done = false;
+
+    let mut lines = Vec::new();
+    let deadline = timeout.map(|t| tokio::time::Instant::now() + t);
+
+    let exit_code = loop {
+        let (text, is_stderr) = tokio::select! {
+            line = stdout_reader.next_line(), if !stdout_done => match line {
+                Ok(Some(text)) => (text, false),
+                _ => {
+                    stdout_done = true;
+                    continue;
+                }
+            },
+            line = stderr_reader.next_line(), if !stderr_done => match line {
+                Ok(Some(text)) => (text, true),
+                _ => {
+                    stderr_done = true;
+                    continue;
+                }
+            },
+            status = child.wait(), if stdout_done && stderr_done => {
+                group.release();
+                break status.ok().and_then(|s| s.code());
+            }
+            _ = cancel.cancelled() => {
+                kill_tree(&mut child, &mut group).await;
+                return Err(Error::Cancelled);
+            }
+            _ = sleep_until(deadline) => {
+                kill_tree(&mut child, &mut group).await;
+                return Ok(ShellOutput {
+                    exit_code: None,
+                    lines,
+                    timed_out: true,
+                    duration_secs: start.elapsed().as_secs_f64(),
+                });
+            }
+        };
+
+        if let Some(event) = line_event(&text, is_stderr) {
+            let _ = events.send(event).await;
+        }
+        lines.push(text);
+    };
+
+    Ok(ShellOutput {
+        exit_code,
+        lines,
+        timed_out: false,
+        duration_secs: start.elapsed().as_secs_f64(),
+    })
+}
+
+/// Sleep until the deadline, or forever if there is none.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{channel, EventEnvelope};
+
+    #[tokio::test]
+    async fn test_run_shell_captures_output_and_exit_code() {
+        let (tx, _rx) = channel();
+        let env = vec![("WIGGLE_PUPPY_TEST".to_string(), "hello".to_string())];
+        let output = run_shell(
+            "echo \"$WIGGLE_PUPPY_TEST\"; echo oops >&2; exit 3",
+            &env,
+            None,
+            &CancellationToken::new(),
+            &tx,
+            |_, _| None,
+        )
+        .await
+        .expect("shell should run");
+
+        assert_eq!(output.exit_code, Some(3));
+        assert!(!output.success());
+        assert!(output.lines.contains(&"hello".to_string()));
+        assert!(output.lines.contains(&"oops".to_string()));
+        assert_eq!(output.tail(1).lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_shell_streams_lines_as_events() {
+        let (tx, mut rx) = channel();
+        let output = run_shell(
+            "echo one; echo two",
+            &[],
+            None,
+            &CancellationToken::new(),
+            &tx,
+            |line, _| Some(Event::progress(line)),
+        )
+        .await
+        .expect("shell should run");
+        assert!(output.success());
+
+        drop(tx);
+        let mut streamed = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::Progress { message } = event {
+                streamed.push(message);
+            }
+        }
+        assert_eq!(streamed, vec!["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_shell_timeout() {
+        let (tx, _rx) = channel();
+        let start = Instant::now();
+        let output = run_shell(
+            "echo started; sleep 30",
+            &[],
+            Some(Duration::from_millis(300)),
+            &CancellationToken::new(),
+            &tx,
+            |_, _| None,
+        )
+        .await
+        .expect("shell should run");
+
+        assert!(output.timed_out);
+        assert!(!output.success());
+        assert_eq!(output.lines, vec!["started"]);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_kill_tree_releases_group() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 30"]);
+        isolate(&mut cmd);
+        let mut child = cmd.spawn().expect("sh should spawn");
+        let mut group = ProcessGroup::of(&child);
+
+        kill_tree(&mut child, &mut group).await;
+        // The child is reaped, so dropping the guard must not signal its old id
+        assert!(group.pgid.is_none());
+        assert!(child
+            .try_wait()
+            .expect("child should be waitable")
+            .is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminate_waits_for_exit_then_releases_group() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "trap 'exit 0' TERM; while true; do sleep 0.1; done"]);
+        isolate(&mut cmd);
+        let mut child = cmd.spawn().expect("sh should spawn");
+        let mut group = ProcessGroup::of(&child);
+        // Give the shell time to install its trap
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let start = Instant::now();
+        terminate(&mut child, &mut group, Duration::from_secs(10)).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(group.pgid.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_shell_cancelled() {
+        let (tx, _rx) = channel();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = run_shell("sleep 30", &[], None, &cancel, &tx, |_, _| None).await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+}
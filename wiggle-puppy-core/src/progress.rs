@@ -0,0 +1,257 @@
+//! Runner-maintained progress log.
+//!
+//! This module provides `ProgressLog`, which appends one timestamped entry
+//! per iteration to a plain-text file in the same format as the hand-kept
+//! `progress.txt` logs: the story that was next, what got completed, how
+//! long the agent ran, and the tail of its output.
+
+use crate::error::{Error, Result};
+use crate::event::StopReason;
+use chrono::{DateTime, Local};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Header written when the progress log is first created.
+const PROGRESS_HEADER: &str = "# Wiggle Puppy - Progress Log\n\n## Progress\n\n";
+
+/// A story referenced by a progress entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoryRef {
+    /// The story ID.
+    pub id: String,
+    /// The story title.
+    pub title: String,
+}
+
+/// What happened during a single iteration.
+#[derive(Debug, Clone)]
+pub struct ProgressEntry {
+    /// When the iteration finished.
+    pub timestamp: DateTime<Local>,
+    /// The iteration number (1-indexed).
+    pub iteration: u32,
+    /// The story that was next in the PRD when the iteration started.
+    pub next_story: Option<StoryRef>,
+    /// Stories the agent marked as passing during the iteration.
+    pub completed: Vec<StoryRef>,
+    /// Duration of the iteration in seconds, including retries.
+    pub duration_secs: f64,
+    /// Exit code of the last agent run, if it exited normally.
+    pub exit_code: Option<i32>,
+    /// Number of retries after errors or timeouts.
+    pub retries: u32,
+    /// The last few lines of agent output.
+    pub last_lines: Vec<String>,
+    /// Why the run stopped during this iteration, if it did.
+    pub stopped: Option<StopReason>,
+}
+
+impl fmt::Display for ProgressEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] Iteration {}",
+            self.timestamp.format("%Y-%m-%d %H:%M"),
+            self.iteration
+        )?;
+        if let Some(story) = &self.next_story {
+            let status = if self.completed.contains(story) {
+                "COMPLETE"
+            } else {
+                "IN PROGRESS"
+            };
+            write!(f, ": Story {}: {} - {}", story.id, story.title, status)?;
+        }
+        writeln!(f)?;
+
+        if self.completed.is_empty() {
+            writeln!(f, "  Completed: none")?;
+        } else {
+            let completed: Vec<String> = self
+                .completed
+                .iter()
+                .map(|s| format!("{} ({})", s.id, s.title))
+                .collect();
+            writeln!(f, "  Completed: {}", completed.join(", "))?;
+        }
+
+        let exit_code = self
+            .exit_code
+            .map_or_else(|| "none".to_string(), |code| code.to_string());
+        writeln!(
+            f,
+            "  Duration: {:.1}s, exit code: {}, retries: {}",
+            self.duration_secs, exit_code, self.retries
+        )?;
+        if let Some(reason) = &self.stopped {
+            writeln!(f, "  Stopped: {}", reason)?;
+        }
+
+        if !self.last_lines.is_empty() {
+            writeln!(f, "  Last output:")?;
+            for line in &self.last_lines {
+                writeln!(f, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An append-only progress log file.
+#[derive(Debug, Clone)]
+pub struct ProgressLog {
+    /// Path to the log file.
+    path: PathBuf,
+}
+
+impl ProgressLog {
+    /// Create a progress log writing to the given path.
+    ///
+    /// The file is created (with a header) on the first append.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Get the path of the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry, followed by a blank line.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ProgressWriteError` if the file cannot be written.
+    pub fn append(&self, entry: &ProgressEntry) -> Result<()> {
+        let map_err = |source| Error::ProgressWriteError {
+            path: self.path.clone(),
+            source,
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(map_err)?;
+
+        let is_new = file.metadata().map_err(map_err)?.len() == 0;
+        let mut content = String::new();
+        if is_new {
+            content.push_str(PROGRESS_HEADER);
+        }
+        content.push_str(&entry.to_string());
+        content.push('\n');
+
+        file.write_all(content.as_bytes()).map_err(map_err)
+    }
+
+    /// Read the last `n` lines of the log.
+    ///
+    /// Returns an empty string if the log doesn't exist yet or can't be read.
+    pub fn tail(&self, n: usize) -> String {
+        let Ok(content) = std::fs::read_to_string(&self.path) else {
+            return String::new();
+        };
+        let lines: Vec<&str> = content.trim_end().lines().collect();
+        lines[lines.len().saturating_sub(n)..].join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn story(id: &str, title: &str) -> StoryRef {
+        StoryRef {
+            id: id.to_string(),
+            title: title.to_string(),
+        }
+    }
+
+    fn create_test_entry() -> ProgressEntry {
+        ProgressEntry {
+            timestamp: Local.with_ymd_and_hms(2026, 1, 27, 0, 30, 0).unwrap(),
+            iteration: 3,
+            next_story: Some(story("2", "Error types")),
+            completed: vec![story("2", "Error types")],
+            duration_secs: 45.25,
+            exit_code: Some(0),
+            retries: 1,
+            last_lines: vec!["cargo test passed".to_string()],
+            stopped: None,
+        }
+    }
+
+    #[test]
+    fn test_entry_format() {
+        let entry = create_test_entry();
+        assert_eq!(
+            entry.to_string(),
+            "[2026-01-27 00:30] Iteration 3: Story 2: Error types - COMPLETE\n\
+             \x20 Completed: 2 (Error types)\n\
+             \x20 Duration: 45.2s, exit code: 0, retries: 1\n\
+             \x20 Last output:\n\
+             \x20   cargo test passed\n"
+        );
+    }
+
+    #[test]
+    fn test_entry_format_in_progress_without_output() {
+        let mut entry = create_test_entry();
+        entry.completed.clear();
+        entry.exit_code = None;
+        entry.last_lines.clear();
+
+        let text = entry.to_string();
+        assert!(text
+            .starts_with("[2026-01-27 00:30] Iteration 3: Story 2: Error types - IN PROGRESS\n"));
+        assert!(text.contains("  Completed: none\n"));
+        assert!(text.contains("exit code: none"));
+        assert!(!text.contains("Last output"));
+    }
+
+    #[test]
+    fn test_entry_format_stopped() {
+        let mut entry = create_test_entry();
+        entry.stopped = Some(StopReason::Cancelled);
+        assert!(entry
+            .to_string()
+            .contains("  Duration: 45.2s, exit code: 0, retries: 1\n  Stopped: cancelled\n"));
+        assert!(!create_test_entry().to_string().contains("Stopped"));
+    }
+
+    #[test]
+    fn test_entry_format_without_prd() {
+        let mut entry = create_test_entry();
+        entry.next_story = None;
+        assert!(entry
+            .to_string()
+            .starts_with("[2026-01-27 00:30] Iteration 3\n"));
+    }
+
+    #[test]
+    fn test_append_writes_header_once() {
+        let path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_progress_{}.txt",
+            std::process::id()
+        ));
+        std::fs::remove_file(&path).ok();
+
+        let log = ProgressLog::new(&path);
+        let entry = create_test_entry();
+        log.append(&entry).expect("should append");
+        log.append(&entry).expect("should append");
+
+        let content = std::fs::read_to_string(&path).expect("should read log");
+        assert!(content.starts_with(PROGRESS_HEADER));
+        assert_eq!(content.matches("# Wiggle Puppy - Progress Log").count(), 1);
+        assert_eq!(content.matches("Iteration 3").count(), 2);
+
+        assert_eq!(log.tail(2), "  Last output:\n    cargo test passed");
+
+        std::fs::remove_file(&path).ok();
+        assert_eq!(log.tail(2), "");
+    }
+}
@@ -4,15 +4,41 @@
 //! handling prompt re-reading, PRD state tracking, completion detection, and
 //! event emission for consumers like CLI or TUI.
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
 use crate::agent::{Agent, AgentOutput};
+use crate::checkpoint::{self, Checkpoint};
+use crate::completion::{self, CompletionPolicy};
 use crate::config::Config;
 use crate::error::{Error, Result};
-use crate::event::{channel, CompletionReason, Event, EventReceiver, EventSender, StopReason};
-use crate::prd::Prd;
+use crate::event::{
+    channel, CompletionReason, ConfigChange, Event, EventReceiver, EventSender, StopReason,
+};
+use crate::feedback::IterationFeedback;
+use crate::hook::{Hook, HookFailure, HookStage};
+use crate::pattern::{ErrorClass, ErrorPattern};
+use crate::prd::{Prd, StoryChange};
+use crate::process::{run_shell, ShellOutput};
+use crate::progress::{ProgressEntry, ProgressLog, StoryRef};
+use crate::stall::StallDetector;
+use crate::template::TemplateVars;
+
+/// Lines of agent output recorded in each progress log entry.
+const PROGRESS_OUTPUT_LINES: usize = 5;
+
+/// Lines of verify command output carried in events and prompt feedback.
+const VERIFY_OUTPUT_LINES: usize = 50;
+
+/// Lines of the progress log available to prompt templates as `progress_tail`.
+const TEMPLATE_PROGRESS_LINES: usize = 20;
 
 /// Calculate exponential backoff duration
 fn calculate_backoff(attempt: u32, config: &Config) -> u64 {
@@ -21,6 +47,148 @@ fn calculate_backoff(attempt: u32, config: &Config) -> u64 {
     backoff as u64
 }
 
+/// Build the prompt section describing a failed verify command.
+fn verify_feedback(command: &str, output: &ShellOutput, timeout_secs: u64) -> String {
+    let result = if output.timed_out {
+        format!("timed out after {} seconds", timeout_secs)
+    } else {
+        match output.exit_code {
+            Some(code) => format!("exited with code {}", code),
+            None => "was killed by a signal".to_string(),
+        }
+    };
+    format!(
+        "## Verification failed\n\n\
+         The work was not accepted as complete because the verify command \
+         `{}` {}. Fix the problems below before signalling completion again.\n\n\
+         ```\n{}\n```",
+        command,
+        result,
+        output.tail(VERIFY_OUTPUT_LINES)
+    )
+}
+
+/// Build the prompt template variables for an iteration.
+///
+/// Starts from the custom variables in `config`; built-ins replace any of
+/// the same name.
+fn template_vars(
+    config: &Config,
+    iteration: u32,
+    prd: Option<&Prd>,
+    progress: Option<&ProgressLog>,
+    last_error: Option<&str>,
+) -> TemplateVars {
+    let mut vars: TemplateVars = config.template_vars.iter().collect();
+    let story = prd.and_then(Prd::next_story);
+    let criteria = story.map(|story| {
+        story
+            .acceptance_criteria
+            .iter()
+            .map(|criterion| format!("- {}", criterion))
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    let completed = prd.map(|prd| prd.stories.iter().filter(|s| s.passes).count());
+
+    vars.set("iteration", iteration.to_string())
+        .set("max_iterations", config.max_iterations.to_string())
+        .set(
+            "next_story.id",
+            story.map(|s| s.id.as_str()).unwrap_or_default(),
+        )
+        .set(
+            "next_story.title",
+            story.map(|s| s.title.as_str()).unwrap_or_default(),
+        )
+        .set(
+            "next_story.description",
+            story.map(|s| s.description.as_str()).unwrap_or_default(),
+        )
+        .set(
+            "next_story.acceptance_criteria",
+            criteria.unwrap_or_default(),
+        )
+        .set(
+            "prd.completed",
+            completed.map(|n| n.to_string()).unwrap_or_default(),
+        )
+        .set(
+            "prd.total",
+            prd.map(|prd| prd.stories.len().to_string())
+                .unwrap_or_default(),
+        )
+        .set(
+            "progress_tail",
+            progress
+                .map(|log| log.tail(TEMPLATE_PROGRESS_LINES))
+                .unwrap_or_default(),
+        )
+        .set("last_error", last_error.unwrap_or_default());
+    vars
+}
+
+/// Build the progress log entry for an iteration.
+///
+/// Stories completed during the iteration and the stop reason are left
+/// empty for the caller to fill in.
+fn progress_entry(
+    iteration: u32,
+    started: Instant,
+    prd: Option<&Prd>,
+    retries: u32,
+    output: Option<&AgentOutput>,
+) -> ProgressEntry {
+    ProgressEntry {
+        timestamp: chrono::Local::now(),
+        iteration,
+        next_story: prd.and_then(Prd::next_story).map(|story| StoryRef {
+            id: story.id.clone(),
+            title: story.title.clone(),
+        }),
+        completed: Vec::new(),
+        duration_secs: started.elapsed().as_secs_f64(),
+        exit_code: output.and_then(|output| output.exit_code),
+        retries,
+        last_lines: output
+            .map(|output| {
+                output
+                    .last_lines(PROGRESS_OUTPUT_LINES)
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        stopped: None,
+    }
+}
+
+/// Format error patterns for a `ConfigChange`.
+fn format_patterns(patterns: &[ErrorPattern]) -> String {
+    let formatted: Vec<String> = patterns.iter().map(ToString::to_string).collect();
+    format!("[{}]", formatted.join(", "))
+}
+
+/// What the loop does after a stage's hooks have run.
+enum HookStep {
+    /// Carry on with the iteration.
+    Continue,
+    /// Run the iteration again; the backoff has already been waited out.
+    Retry,
+    /// Stop the run.
+    Stop(StopReason),
+}
+
+/// What the loop does after the verify command has checked a completion.
+enum Verdict {
+    /// Accept the completion.
+    Complete,
+    /// Keep looping; the verify failure is stored for the next prompt.
+    Continue,
+    /// Stop the run.
+    Stop(StopReason),
+}
+
 /// The main runner that executes the agent loop.
 ///
 /// The runner manages the lifecycle of agent invocations, re-reading the prompt
@@ -32,8 +200,89 @@ pub struct Runner {
     config: Config,
     /// Event sender for communicating with consumers.
     events: EventSender,
-    /// Shared cancellation flag.
-    cancel_flag: Arc<AtomicBool>,
+    /// Shared cancellation token.
+    cancel: CancellationToken,
+    /// Shared token set when a stop after the current iteration is requested.
+    stop_requested: CancellationToken,
+    /// Shared pause and suspend requests, plus the published state.
+    control: Arc<Control>,
+}
+
+/// What the runner is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RunnerState {
+    /// Not started yet.
+    #[default]
+    Idle,
+    /// Running an iteration (reading state, running the agent).
+    Running,
+    /// Paused between iterations, or with the agent suspended.
+    Paused,
+    /// Waiting before retrying a failed agent run.
+    BackingOff,
+    /// Sleeping between iterations or until a usage limit resets.
+    Sleeping,
+    /// The run has completed or stopped.
+    Finished,
+}
+
+impl std::fmt::Display for RunnerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunnerState::Idle => write!(f, "idle"),
+            RunnerState::Running => write!(f, "running"),
+            RunnerState::Paused => write!(f, "paused"),
+            RunnerState::BackingOff => write!(f, "backing off"),
+            RunnerState::Sleeping => write!(f, "sleeping"),
+            RunnerState::Finished => write!(f, "finished"),
+        }
+    }
+}
+
+/// Pause controls and state shared between the runner and its handles.
+#[derive(Debug)]
+struct Control {
+    /// Set while a pause is requested; honoured between iterations.
+    paused: watch::Sender<bool>,
+    /// Set while the running agent should be suspended.
+    suspended: watch::Sender<bool>,
+    /// The runner's current state.
+    state: watch::Sender<RunnerState>,
+    /// Settings changed through the handle, applied at the next iteration boundary.
+    updates: Mutex<ConfigUpdate>,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Self {
+            paused: watch::Sender::new(false),
+            suspended: watch::Sender::new(false),
+            state: watch::Sender::new(RunnerState::Idle),
+            updates: Mutex::new(ConfigUpdate::default()),
+        }
+    }
+}
+
+impl Control {
+    /// Record a pending settings change; later calls override earlier ones.
+    fn update(&self, apply: impl FnOnce(&mut ConfigUpdate)) {
+        let mut updates = self.updates.lock().unwrap_or_else(|e| e.into_inner());
+        apply(&mut updates);
+    }
+
+    /// Take all pending settings changes.
+    fn take_updates(&self) -> ConfigUpdate {
+        std::mem::take(&mut *self.updates.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Settings that can be changed while the runner is running.
+#[derive(Debug, Default)]
+struct ConfigUpdate {
+    max_iterations: Option<u32>,
+    delay: Option<Duration>,
+    error_patterns: Option<Vec<ErrorPattern>>,
+    completion_phrase: Option<String>,
 }
 
 /// Handle for controlling a running runner instance.
@@ -41,24 +290,118 @@ pub struct Runner {
 /// This handle can be used to cancel the runner from another task or thread.
 #[derive(Debug, Clone)]
 pub struct RunnerHandle {
-    /// Shared cancellation flag.
-    cancel_flag: Arc<AtomicBool>,
+    /// Shared cancellation token.
+    cancel: CancellationToken,
+    /// Shared token set when a stop after the current iteration is requested.
+    stop_requested: CancellationToken,
+    /// Shared pause and suspend requests, plus the published state.
+    control: Arc<Control>,
 }
 
 impl RunnerHandle {
-    /// Signal the runner to cancel at the next opportunity.
+    /// Cancel the runner immediately.
+    ///
+    /// A running agent is sent SIGTERM (then SIGKILL after the kill grace
+    /// period), and any delay or backoff sleep wakes up at once.
     pub fn cancel(&self) {
-        self.cancel_flag.store(true, Ordering::SeqCst);
+        self.cancel.cancel();
     }
 
     /// Check if cancellation has been requested.
     pub fn is_cancelled(&self) -> bool {
-        self.cancel_flag.load(Ordering::SeqCst)
+        self.cancel.is_cancelled()
+    }
+
+    /// Wait until cancellation has been requested.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// Ask the runner to stop once the current iteration finishes.
+    ///
+    /// The running agent is left alone; the runner stops with
+    /// `StopReason::Cancelled` at the next iteration boundary, skipping
+    /// any remaining delay. Failed attempts are not retried, and retry
+    /// backoff and usage limit sleeps end at once.
+    pub fn stop_after_iteration(&self) {
+        self.stop_requested.cancel();
+    }
+
+    /// Check if a stop after the current iteration has been requested.
+    pub fn is_stop_requested(&self) -> bool {
+        self.stop_requested.is_cancelled()
+    }
+
+    /// Pause the runner before its next iteration.
+    ///
+    /// A running agent is left to finish; the runner then waits, emitting
+    /// `Event::Paused`, until `resume` is called.
+    pub fn pause(&self) {
+        self.control.paused.send_replace(true);
+    }
+
+    /// Pause the runner and suspend the running agent right away.
+    ///
+    /// The agent's process group is stopped with SIGSTOP and continued with
+    /// SIGCONT on `resume`; its timeouts don't run while it is suspended.
+    /// Outside an agent run this behaves like `pause`.
+    pub fn suspend(&self) {
+        self.control.paused.send_replace(true);
+        self.control.suspended.send_replace(true);
+    }
+
+    /// Resume after `pause` or `suspend`.
+    pub fn resume(&self) {
+        self.control.suspended.send_replace(false);
+        self.control.paused.send_replace(false);
+    }
+
+    /// Check if a pause has been requested and not yet resumed.
+    pub fn is_paused(&self) -> bool {
+        *self.control.paused.borrow()
+    }
+
+    /// Change the maximum number of iterations from the next iteration on.
+    ///
+    /// Lowering it to the number of iterations already run stops the runner
+    /// with `StopReason::MaxIterations` at the next iteration boundary.
+    pub fn set_max_iterations(&self, max: u32) {
+        self.control.update(|u| u.max_iterations = Some(max));
+    }
+
+    /// Change the delay between iterations from the next iteration on.
+    pub fn set_delay(&self, delay: Duration) {
+        self.control.update(|u| u.delay = Some(delay));
+    }
+
+    /// Replace the error patterns from the next iteration on.
+    pub fn set_error_patterns(&self, patterns: Vec<ErrorPattern>) {
+        self.control.update(|u| u.error_patterns = Some(patterns));
+    }
+
+    /// Change the completion phrase from the next iteration on.
+    pub fn set_completion_phrase(&self, phrase: impl Into<String>) {
+        let phrase = phrase.into();
+        self.control.update(|u| u.completion_phrase = Some(phrase));
+    }
+
+    /// Get what the runner is currently doing.
+    pub fn state(&self) -> RunnerState {
+        let state = *self.control.state.borrow();
+        if state == RunnerState::Running && *self.control.suspended.borrow() {
+            RunnerState::Paused
+        } else {
+            state
+        }
     }
 }
 
 /// The outcome of a runner execution.
-#[derive(Debug, Clone)]
+///
+/// Serializes like the reasons it carries, tagged by an `outcome` field:
+/// `{"outcome": "completed", "iterations": 3, "reason": {"type": "both"}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
 pub enum Outcome {
     /// The runner completed successfully.
     Completed {
@@ -117,260 +460,1001 @@ impl Runner {
     /// ```
     pub fn new(config: Config) -> (Self, EventReceiver, RunnerHandle) {
         let (tx, rx) = channel();
-        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let cancel = CancellationToken::new();
+        let stop_requested = CancellationToken::new();
+        let control = Arc::new(Control::default());
 
         let runner = Self {
             config,
             events: tx,
-            cancel_flag: cancel_flag.clone(),
+            cancel: cancel.clone(),
+            stop_requested: stop_requested.clone(),
+            control: control.clone(),
         };
 
-        let handle = RunnerHandle { cancel_flag };
+        let handle = RunnerHandle {
+            cancel,
+            stop_requested,
+            control,
+        };
 
         (runner, rx, handle)
     }
 
+    /// The id stamped on every event envelope from this runner.
+    ///
+    /// A fresh run keeps the id it was created with. A run resumed from a
+    /// checkpoint takes the checkpoint's id once `run` restores it, so it is
+    /// only final after `run` has started.
+    pub fn run_id(&self) -> Uuid {
+        self.events.run_id()
+    }
+
     /// Check if cancellation has been requested.
     fn is_cancelled(&self) -> bool {
-        self.cancel_flag.load(Ordering::SeqCst)
+        self.cancel.is_cancelled()
     }
 
-    /// Run the main agent loop.
-    ///
-    /// This method executes the following loop:
-    /// 1. Re-read the prompt file (for stateful prompts)
-    /// 2. Re-read the PRD file (to detect agent updates)
-    /// 3. Check if PRD is complete
-    /// 4. Spawn the agent with the prompt
-    /// 5. Check for completion phrase in output
-    /// 6. Delay before next iteration
-    /// 7. Repeat until completion or max iterations
-    ///
-    /// # Returns
+    /// Check if the loop should stop at this iteration boundary.
+    fn should_stop(&self) -> bool {
+        self.is_cancelled() || self.stop_requested.is_cancelled()
+    }
+
+    /// Publish the runner's current state.
+    fn set_state(&self, state: RunnerState) {
+        self.control.state.send_replace(state);
+    }
+
+    /// Wait while a pause is requested, emitting `Paused`/`Resumed`.
     ///
-    /// Returns an `Outcome` indicating whether the runner completed successfully
-    /// or stopped for some reason (max iterations, cancellation, error).
-    pub async fn run(&self) -> Result<Outcome> {
+    /// Returns `false` if the run was cancelled or asked to stop while paused.
+    async fn wait_while_paused(&self) -> bool {
+        let mut paused = self.control.paused.subscribe();
+        if !*paused.borrow_and_update() {
+            return true;
+        }
+
+        let paused_at = std::time::Instant::now();
+        self.set_state(RunnerState::Paused);
         let _ = self
             .events
-            .send(Event::Started {
-                max_iterations: self.config.max_iterations,
+            .send(Event::Paused {
+                agent_suspended: false,
             })
             .await;
 
-        let agent = Agent::new(
-            &self.config.agent_command,
-            self.config.agent_args.clone(),
-            self.config.error_patterns.clone(),
-            self.config.agent_timeout_secs,
-        );
+        loop {
+            tokio::select! {
+                _ = paused.changed() => {
+                    if !*paused.borrow_and_update() {
+                        break;
+                    }
+                }
+                _ = self.cancel.cancelled() => return false,
+                _ = self.stop_requested.cancelled() => return false,
+            }
+        }
+
+        self.set_state(RunnerState::Running);
+        let _ = self
+            .events
+            .send(Event::Resumed {
+                paused_secs: paused_at.elapsed().as_secs_f64(),
+            })
+            .await;
+        true
+    }
 
-        let mut iteration: u32 = 0;
-        let mut consecutive_failures: u32 = 0;
+    /// Build the agent for the given configuration.
+    fn build_agent(&self, config: &Config) -> Agent {
+        Agent::new(
+            &config.agent_command,
+            config.agent_args.clone(),
+            config.error_patterns.clone(),
+            config.agent_timeout_secs,
+        )
+        .idle_timeout_secs(config.agent_idle_timeout_secs)
+        .usage_limit_wait_secs(config.usage_limit_wait_secs)
+        .prompt_delivery(config.prompt_delivery)
+        .cancellation(self.cancel.clone())
+        .kill_grace_secs(config.agent_kill_grace_secs)
+        .suspension(self.control.suspended.subscribe())
+    }
 
-        loop {
-            // Check cancellation before starting iteration
-            if self.is_cancelled() {
+    /// Apply settings changed through the handle since the last iteration.
+    ///
+    /// Emits `Event::ConfigChanged` listing the settings whose value changed.
+    /// Returns `true` if the error patterns changed and the agent needs rebuilding.
+    async fn apply_updates(&self, config: &mut Config) -> bool {
+        let update = self.control.take_updates();
+        let mut changes = Vec::new();
+        let mut patterns_changed = false;
+
+        if let Some(max) = update.max_iterations {
+            if max != config.max_iterations {
+                changes.push(ConfigChange::new(
+                    "max_iterations",
+                    config.max_iterations,
+                    max,
+                ));
+                config.max_iterations = max;
+            }
+        }
+        if let Some(delay) = update.delay {
+            if delay != config.delay {
+                changes.push(ConfigChange::new(
+                    "delay",
+                    format!("{:?}", config.delay),
+                    format!("{:?}", delay),
+                ));
+                config.delay = delay;
+            }
+        }
+        if let Some(patterns) = update.error_patterns {
+            if patterns != config.error_patterns {
+                changes.push(ConfigChange::new(
+                    "error_patterns",
+                    format_patterns(&config.error_patterns),
+                    format_patterns(&patterns),
+                ));
+                config.error_patterns = patterns;
+                patterns_changed = true;
+            }
+        }
+        if let Some(phrase) = update.completion_phrase {
+            if phrase != config.completion_phrase {
+                changes.push(ConfigChange::new(
+                    "completion_phrase",
+                    &config.completion_phrase,
+                    &phrase,
+                ));
+                config.completion_phrase = phrase;
+            }
+        }
+
+        if !changes.is_empty() {
+            let _ = self.events.send(Event::ConfigChanged { changes }).await;
+        }
+        patterns_changed
+    }
+
+    /// Load the checkpoint to resume from, or start a fresh one.
+    ///
+    /// Returns the checkpoint, whether it was restored from disk, and whether
+    /// a settings mismatch was overridden by `force_resume`. A missing
+    /// checkpoint file starts a fresh run even when resuming.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checkpoint cannot be read, or if it was written
+    /// with different settings and `force_resume` is not set.
+    fn restore_checkpoint(&self, config: &Config) -> Result<(Checkpoint, bool, bool)> {
+        let path = match &config.checkpoint_path {
+            Some(path) if config.resume && path.exists() => path,
+            _ => return Ok((Checkpoint::new(config, self.events.run_id()), false, false)),
+        };
+
+        let mut restored = Checkpoint::load(path)?;
+        let expected = checkpoint::config_hash(config);
+        let forced = restored.config_hash != expected;
+        if forced {
+            if !config.force_resume {
+                return Err(Error::CheckpointMismatch {
+                    path: path.clone(),
+                    expected,
+                    found: restored.config_hash,
+                });
+            }
+            restored.config_hash = expected;
+        }
+        Ok((restored, true, forced))
+    }
+
+    /// Load the PRD if one is configured, emitting `PrdUpdated` on success.
+    ///
+    /// Read failures are reported as a warning prefixed with `failure` and
+    /// treated as if no PRD were configured.
+    async fn load_prd(&self, config: &Config, failure: &str) -> Option<Prd> {
+        let prd_path = config.prd_path.as_ref()?;
+        match Prd::load(prd_path) {
+            Ok(prd) => {
+                let completed = prd.stories.iter().filter(|s| s.passes).count();
+                let total = prd.stories.len();
                 let _ = self
                     .events
-                    .send(Event::Stopped {
-                        iterations: iteration,
-                        reason: StopReason::Cancelled,
-                    })
+                    .send(Event::PrdUpdated { completed, total })
                     .await;
-                return Ok(Outcome::Stopped {
-                    iterations: iteration,
-                    reason: StopReason::Cancelled,
-                });
+                Some(prd)
             }
-
-            // Check max iterations
-            if iteration >= self.config.max_iterations {
+            Err(e) => {
                 let _ = self
                     .events
-                    .send(Event::Stopped {
-                        iterations: iteration,
-                        reason: StopReason::MaxIterations,
-                    })
+                    .send(Event::warning(format!("{}: {}", failure, e)))
                     .await;
-                return Ok(Outcome::Stopped {
-                    iterations: iteration,
-                    reason: StopReason::MaxIterations,
-                });
+                None
             }
+        }
+    }
 
-            iteration += 1;
+    /// Sleep for the given duration, waking early if cancelled or asked to stop.
+    ///
+    /// Used for backoff and usage limit sleeps, which can last hours; a stop
+    /// request ends them rather than waiting for the retry. Returns `false`
+    /// if the run should stop.
+    async fn sleep_unless_stopped(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => !self.should_stop(),
+            _ = self.cancel.cancelled() => false,
+            _ = self.stop_requested.cancelled() => false,
+        }
+    }
+
+    /// Emit `Event::Stopped` and return the matching outcome.
+    async fn stop(&self, iterations: u32, reason: StopReason) -> Result<Outcome> {
+        self.set_state(RunnerState::Finished);
+        let _ = self
+            .events
+            .send(Event::Stopped {
+                iterations,
+                reason: reason.clone(),
+            })
+            .await;
+        Ok(Outcome::Stopped { iterations, reason })
+    }
+
+    /// Record the iteration that stopped the run, then stop.
+    ///
+    /// Used when the run stops partway through an iteration, which would
+    /// otherwise leave no trace of that iteration in the progress log, and
+    /// leave stale counters in the checkpoint for a resumed run.
+    async fn stop_early(
+        &self,
+        config: &Config,
+        checkpoint: &mut Checkpoint,
+        consecutive_failures: u32,
+        mut entry: ProgressEntry,
+        iterations: u32,
+        reason: StopReason,
+    ) -> Result<Outcome> {
+        entry.stopped = Some(reason.clone());
+        self.record_progress(config, &entry).await;
+        checkpoint.update(iterations, consecutive_failures);
+        self.save_checkpoint(config, checkpoint).await;
+        self.stop(iterations, reason).await
+    }
+
+    /// Record in the checkpoint that the run's work is over, then emit the
+    /// outcome's event.
+    ///
+    /// Used when the run completes or the agent reports it is blocked or
+    /// waiting for input, so resuming the checkpoint doesn't start the agent
+    /// again.
+    async fn finish(
+        &self,
+        config: &Config,
+        checkpoint: &mut Checkpoint,
+        outcome: Outcome,
+    ) -> Result<Outcome> {
+        checkpoint.finished = Some(outcome.clone());
+        self.save_checkpoint(config, checkpoint).await;
+        self.report(outcome).await
+    }
+
+    /// Emit the event matching an outcome and return it.
+    async fn report(&self, outcome: Outcome) -> Result<Outcome> {
+        match outcome {
+            Outcome::Completed { iterations, reason } => self.complete(iterations, reason).await,
+            Outcome::Stopped { iterations, reason } => self.stop(iterations, reason).await,
+        }
+    }
 
+    /// Append an entry to the progress log, if one is configured.
+    async fn record_progress(&self, config: &Config, entry: &ProgressEntry) {
+        let Some(path) = &config.progress_path else {
+            return;
+        };
+        if let Err(e) = ProgressLog::new(path).append(entry) {
             let _ = self
                 .events
-                .send(Event::IterationStarted {
-                    iteration,
-                    max_iterations: self.config.max_iterations,
-                })
+                .send(Event::warning(format!(
+                    "failed to write progress log: {}",
+                    e
+                )))
                 .await;
+        }
+    }
 
-            // Re-read prompt each iteration for stateful prompts
-            let prompt = match self.config.get_prompt() {
-                Ok(p) => p,
-                Err(e) => {
-                    let message = format!("failed to read prompt: {}", e);
+    /// Save the checkpoint, if one is configured.
+    async fn save_checkpoint(&self, config: &Config, checkpoint: &Checkpoint) {
+        let Some(path) = &config.checkpoint_path else {
+            return;
+        };
+        if let Err(e) = checkpoint.save(path) {
+            let _ = self
+                .events
+                .send(Event::warning(format!("failed to save checkpoint: {}", e)))
+                .await;
+        }
+    }
+
+    /// Emit `Event::Completed` and return the matching outcome.
+    async fn complete(&self, iterations: u32, reason: CompletionReason) -> Result<Outcome> {
+        self.set_state(RunnerState::Finished);
+        let _ = self
+            .events
+            .send(Event::Completed {
+                iterations,
+                reason: reason.clone(),
+            })
+            .await;
+        Ok(Outcome::Completed { iterations, reason })
+    }
+
+    /// Run a stage's hooks in order and apply their failure policies.
+    ///
+    /// `retries` counts the times the current iteration has been retried
+    /// because of a hook; it is incremented when a retry is scheduled.
+    async fn run_hooks(
+        &self,
+        config: &Config,
+        stage: HookStage,
+        hooks: &[Hook],
+        env: &[(String, String)],
+        retries: &mut u32,
+    ) -> HookStep {
+        for hook in hooks {
+            let message = match hook.run(stage, env, &self.cancel, &self.events).await {
+                Ok(output) if output.success() => continue,
+                Ok(output) => hook.failure_message(stage, &output),
+                Err(Error::Cancelled) => return HookStep::Stop(StopReason::Cancelled),
+                Err(e) => format!("{} hook '{}' could not run: {}", stage, hook.command, e),
+            };
+
+            match hook.on_failure {
+                HookFailure::Ignore => {}
+                HookFailure::Warn => {
+                    let _ = self.events.send(Event::warning(message)).await;
+                }
+                HookFailure::RetryIteration if *retries < config.max_retries => {
                     let _ = self
                         .events
-                        .send(Event::Stopped {
-                            iterations: iteration,
-                            reason: StopReason::FatalError {
-                                message: message.clone(),
-                            },
+                        .send(Event::warning(format!("{}; retrying iteration", message)))
+                        .await;
+                    *retries += 1;
+                    let backoff = calculate_backoff(*retries, config);
+                    let _ = self
+                        .events
+                        .send(Event::RetryScheduled {
+                            backoff_secs: backoff,
+                            attempt: *retries,
+                            max_retries: config.max_retries,
                         })
                         .await;
-                    return Ok(Outcome::Stopped {
-                        iterations: iteration,
-                        reason: StopReason::FatalError { message },
-                    });
-                }
-            };
-
-            // Check PRD state before running agent (if configured)
-            let prd_complete_before = if let Some(prd_path) = &self.config.prd_path {
-                match Prd::load(prd_path) {
-                    Ok(prd) => {
-                        let completed = prd.stories.iter().filter(|s| s.passes).count();
-                        let total = prd.stories.len();
-                        let _ = self
-                            .events
-                            .send(Event::PrdUpdated { completed, total })
-                            .await;
-                        prd.is_complete()
-                    }
-                    Err(e) => {
-                        let _ = self
-                            .events
-                            .send(Event::warning(format!("failed to read PRD: {}", e)))
-                            .await;
-                        false
+                    self.set_state(RunnerState::BackingOff);
+                    if !self
+                        .sleep_unless_stopped(Duration::from_secs(backoff))
+                        .await
+                    {
+                        return HookStep::Stop(StopReason::Cancelled);
                     }
+                    self.set_state(RunnerState::Running);
+                    return HookStep::Retry;
                 }
-            } else {
-                false
-            };
-
-            // If PRD is already complete before running, we're done
-            if prd_complete_before {
-                let _ = self
-                    .events
-                    .send(Event::Completed {
-                        iterations: iteration - 1, // Haven't run this iteration yet
-                        reason: CompletionReason::AllStoriesComplete,
-                    })
-                    .await;
-                return Ok(Outcome::Completed {
-                    iterations: iteration - 1,
-                    reason: CompletionReason::AllStoriesComplete,
-                });
-            }
-
-            // Run the agent with retry logic
-            let mut retry_attempt = 0u32;
-            let output = loop {
-                // Check circuit breaker
-                if self.config.circuit_breaker_threshold > 0
-                    && consecutive_failures >= self.config.circuit_breaker_threshold
-                {
+                HookFailure::RetryIteration => {
                     let _ = self
                         .events
-                        .send(Event::Stopped {
-                            iterations: iteration,
-                            reason: StopReason::CircuitBreakerTriggered {
-                                consecutive_failures,
-                            },
-                        })
+                        .send(Event::warning(format!("{}; no retries left", message)))
                         .await;
-                    return Ok(Outcome::Stopped {
-                        iterations: iteration,
-                        reason: StopReason::CircuitBreakerTriggered {
-                            consecutive_failures,
-                        },
-                    });
                 }
+                HookFailure::Stop => return HookStep::Stop(StopReason::FatalError { message }),
+            }
+        }
+        HookStep::Continue
+    }
 
-                match agent.run(&prompt, &self.events).await {
-                    Ok(output) => {
-                        consecutive_failures = 0; // Reset on success
-                        break output;
-                    }
-                    Err(Error::AgentErrorDetected { .. }) | Err(Error::AgentTimeout { .. }) => {
-                        retry_attempt += 1;
-                        consecutive_failures += 1;
+    /// Run the verify command, if one is configured.
+    ///
+    /// Returns `None` if completion can be accepted, or feedback for the next
+    /// prompt if the command failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command could not be started or the run was
+    /// cancelled while it ran.
+    async fn verify(&self, config: &Config) -> Result<Option<String>> {
+        let Some(command) = &config.verify_command else {
+            return Ok(None);
+        };
 
-                        if retry_attempt > self.config.max_retries {
-                            // Give up on this iteration, continue to next
-                            // (circuit breaker will catch persistent failures)
-                            break AgentOutput::empty();
-                        }
+        let _ = self
+            .events
+            .send(Event::VerifyStarted {
+                command: command.clone(),
+            })
+            .await;
 
-                        let backoff = calculate_backoff(retry_attempt, &self.config);
-                        let _ = self
-                            .events
-                            .send(Event::RetryScheduled {
-                                backoff_secs: backoff,
-                                attempt: retry_attempt,
-                                max_retries: self.config.max_retries,
-                            })
-                            .await;
-                        tokio::time::sleep(Duration::from_secs(backoff)).await;
-                    }
-                    Err(e) => {
-                        // Other errors (AgentNotFound, etc.) - fatal, don't retry
-                        let message = format!("agent failed: {}", e);
-                        let _ = self
-                            .events
-                            .send(Event::Stopped {
-                                iterations: iteration,
-                                reason: StopReason::FatalError {
-                                    message: message.clone(),
-                                },
-                            })
+        let timeout = (config.verify_timeout_secs > 0)
+            .then(|| Duration::from_secs(config.verify_timeout_secs));
+        let output = run_shell(command, &[], timeout, &self.cancel, &self.events, |_, _| {
+            None
+        })
+        .await?;
+
+        let success = output.success();
+        let _ = self
+            .events
+            .send(Event::VerifyFinished {
+                command: command.clone(),
+                exit_code: output.exit_code,
+                success,
+                timed_out: output.timed_out,
+                output_tail: output.tail(VERIFY_OUTPUT_LINES),
+                duration_secs: output.duration_secs,
+            })
+            .await;
+
+        Ok((!success).then(|| verify_feedback(command, &output, config.verify_timeout_secs)))
+    }
+
+    /// Check a completion with the verify command (if any).
+    ///
+    /// A verify failure is stored in `feedback` for the next prompt.
+    async fn verify_completion(&self, config: &Config, feedback: &mut Option<String>) -> Verdict {
+        match self.verify(config).await {
+            Ok(None) => Verdict::Complete,
+            Ok(Some(failure)) => {
+                *feedback = Some(failure);
+                Verdict::Continue
+            }
+            Err(Error::Cancelled) => Verdict::Stop(StopReason::Cancelled),
+            Err(e) => {
+                let message = format!("verify command failed: {}", e);
+                Verdict::Stop(StopReason::FatalError { message })
+            }
+        }
+    }
+
+    /// Run the main agent loop.
+    ///
+    /// This method executes the following loop:
+    /// 1. Re-read the prompt file (for stateful prompts)
+    /// 2. Re-read the PRD file (to detect agent updates)
+    /// 3. Check if PRD is complete
+    /// 4. Spawn the agent with the prompt
+    /// 5. Check for completion phrase in output
+    /// 6. Run the verify command before accepting completion
+    /// 7. Delay before next iteration
+    /// 8. Repeat until completion or max iterations
+    ///
+    /// If the verify command fails, its output is added to the next prompt.
+    ///
+    /// # Returns
+    ///
+    /// Returns an `Outcome` indicating whether the runner completed successfully
+    /// or stopped for some reason (max iterations, cancellation, error).
+    pub async fn run(&self) -> Result<Outcome> {
+        // Local copy so settings changed through the handle can be applied
+        let mut config = self.config.clone();
+
+        if config.completion_policy == CompletionPolicy::PrdOnly && config.prd_path.is_none() {
+            return Err(Error::config_error(
+                "completion policy prd-only requires a PRD file",
+            ));
+        }
+
+        let (mut checkpoint, resumed, forced) = self.restore_checkpoint(&config)?;
+        // A resumed run keeps its id, on its events as well as in the checkpoint
+        self.events.set_run_id(checkpoint.run_id);
+
+        let _ = self
+            .events
+            .send(Event::Started {
+                max_iterations: config.max_iterations,
+            })
+            .await;
+
+        if forced {
+            let _ = self
+                .events
+                .send(Event::warning(
+                    "checkpoint was written with different settings; resuming anyway",
+                ))
+                .await;
+        }
+        // A finished run's work is over; report how it ended instead of
+        // starting the agent again
+        if let Some(outcome) = checkpoint.finished.clone() {
+            let _ = self
+                .events
+                .send(Event::warning(
+                    "checkpoint records a finished run; not resuming (start without resume to run again)",
+                ))
+                .await;
+            return self.report(outcome).await;
+        }
+        if resumed {
+            let _ = self
+                .events
+                .send(Event::RunResumed {
+                    run_id: checkpoint.run_id,
+                    iteration: checkpoint.iteration,
+                })
+                .await;
+        }
+
+        let mut agent = self.build_agent(&config);
+        let progress = config.progress_path.as_ref().map(ProgressLog::new);
+        let progress = progress.as_ref();
+        let mut stall = (config.stall_threshold > 0).then(|| {
+            // The runner rewrites these itself every iteration
+            let excluded: Vec<&Path> = [&config.progress_path, &config.checkpoint_path]
+                .into_iter()
+                .flatten()
+                .map(PathBuf::as_path)
+                .collect();
+            StallDetector::new(config.stall_workspace.as_deref(), &excluded)
+        });
+
+        self.set_state(RunnerState::Running);
+
+        let mut iteration: u32 = checkpoint.iteration;
+        let mut consecutive_failures: u32 = checkpoint.consecutive_failures;
+        // Usage limit waits since the agent last ran without hitting the limit
+        let mut usage_limit_waits: u32 = 0;
+        // Output of a failed verify command, added to the next prompt
+        let mut verify_feedback: Option<String> = None;
+        // Times the current iteration has been retried because of a hook
+        let mut hook_retries: u32 = 0;
+        // The last agent error in the previous iteration, for the prompt template
+        let mut last_error: Option<String> = None;
+        // How the previous iteration went, for the feedback section
+        let mut previous: Option<IterationFeedback> = None;
+
+        loop {
+            // Check cancellation before starting iteration
+            if self.should_stop() {
+                return self.stop(iteration, StopReason::Cancelled).await;
+            }
+
+            // Honour a pause request before starting the next iteration
+            if !self.wait_while_paused().await {
+                return self.stop(iteration, StopReason::Cancelled).await;
+            }
+
+            // Apply settings changed mid-run (including while paused)
+            if self.apply_updates(&mut config).await {
+                agent = self.build_agent(&config);
+            }
+
+            // Check max iterations
+            if iteration >= config.max_iterations {
+                return self.stop(iteration, StopReason::MaxIterations).await;
+            }
+
+            iteration += 1;
+            let iteration_start = Instant::now();
+            self.events.set_iteration(iteration);
+            self.events.set_attempt(hook_retries + 1);
+
+            let _ = self
+                .events
+                .send(Event::IterationStarted {
+                    iteration,
+                    max_iterations: config.max_iterations,
+                })
+                .await;
+
+            // Check PRD state before running agent (if configured)
+            let prd_before = self.load_prd(&config, "failed to read PRD").await;
+
+            // If PRD is already complete before running, we're done once it
+            // verifies. Skip verifying again if it just failed last iteration.
+            if config.completion_policy.accepts_prd_alone()
+                && prd_before.as_ref().is_some_and(Prd::is_complete)
+                && verify_feedback.is_none()
+            {
+                // Haven't run this iteration yet
+                match self.verify_completion(&config, &mut verify_feedback).await {
+                    Verdict::Complete => {
+                        let outcome = Outcome::Completed {
+                            iterations: iteration - 1,
+                            reason: CompletionReason::AllStoriesComplete,
+                        };
+                        return self.finish(&config, &mut checkpoint, outcome).await;
+                    }
+                    Verdict::Continue => {}
+                    Verdict::Stop(reason) => {
+                        let entry = progress_entry(
+                            iteration,
+                            iteration_start,
+                            prd_before.as_ref(),
+                            0,
+                            None,
+                        );
+                        return self
+                            .stop_early(
+                                &config,
+                                &mut checkpoint,
+                                consecutive_failures,
+                                entry,
+                                iteration - 1,
+                                reason,
+                            )
                             .await;
-                        return Ok(Outcome::Stopped {
-                            iterations: iteration,
-                            reason: StopReason::FatalError { message },
-                        });
                     }
                 }
+            }
+
+            // Metadata handed to hooks through the environment
+            let mut hook_env = vec![
+                (
+                    "WIGGLE_PUPPY_RUN_ID".to_string(),
+                    checkpoint.run_id.to_string(),
+                ),
+                ("WIGGLE_PUPPY_ITERATION".to_string(), iteration.to_string()),
+                (
+                    "WIGGLE_PUPPY_MAX_ITERATIONS".to_string(),
+                    config.max_iterations.to_string(),
+                ),
+            ];
+            if let Some(path) = &config.prd_path {
+                hook_env.push((
+                    "WIGGLE_PUPPY_PRD_PATH".to_string(),
+                    path.display().to_string(),
+                ));
+            }
+            if let Some(story) = prd_before.as_ref().and_then(Prd::next_story) {
+                hook_env.push(("WIGGLE_PUPPY_NEXT_STORY_ID".to_string(), story.id.clone()));
+                hook_env.push((
+                    "WIGGLE_PUPPY_NEXT_STORY_TITLE".to_string(),
+                    story.title.clone(),
+                ));
+            }
+
+            let mut pre_env = hook_env.clone();
+            pre_env.push((
+                "WIGGLE_PUPPY_HOOK_STAGE".to_string(),
+                HookStage::PreIteration.to_string(),
+            ));
+            match self
+                .run_hooks(
+                    &config,
+                    HookStage::PreIteration,
+                    &config.pre_iteration_hooks,
+                    &pre_env,
+                    &mut hook_retries,
+                )
+                .await
+            {
+                HookStep::Continue => {}
+                HookStep::Retry => {
+                    iteration -= 1;
+                    continue;
+                }
+                HookStep::Stop(reason) => {
+                    let entry =
+                        progress_entry(iteration, iteration_start, prd_before.as_ref(), 0, None);
+                    return self
+                        .stop_early(
+                            &config,
+                            &mut checkpoint,
+                            consecutive_failures,
+                            entry,
+                            iteration,
+                            reason,
+                        )
+                        .await;
+                }
+            }
+
+            // The phrase the agent is told to print, with this run's nonce
+            let completion_phrase = if config.completion_nonce {
+                completion::phrase_with_nonce(&config.completion_phrase, checkpoint.run_id)
+            } else {
+                config.completion_phrase.clone()
+            };
+
+            // Re-read and render the prompt each iteration for stateful prompts
+            let vars = template_vars(
+                &config,
+                iteration,
+                prd_before.as_ref(),
+                progress,
+                last_error.as_deref(),
+            );
+            let mut prompt = match config.render_prompt(&vars, &completion_phrase) {
+                Ok(p) => p,
+                Err(e) => {
+                    let message = format!("failed to build prompt: {}", e);
+                    let entry =
+                        progress_entry(iteration, iteration_start, prd_before.as_ref(), 0, None);
+                    return self
+                        .stop_early(
+                            &config,
+                            &mut checkpoint,
+                            consecutive_failures,
+                            entry,
+                            iteration,
+                            StopReason::FatalError { message },
+                        )
+                        .await;
+                }
+            };
+            // Tell the agent how its last attempt went
+            let verify_section = verify_feedback.take();
+            let feedback = match (&previous, config.iteration_feedback) {
+                (Some(previous), true) => Some(previous.render(
+                    &config.feedback_header,
+                    verify_section.as_deref(),
+                    config.feedback_max_chars,
+                )),
+                _ => verify_section,
             };
+            if let Some(feedback) = feedback {
+                prompt = format!("{}\n\n{}", prompt, feedback);
+            }
+
+            // Run the agent with retry logic
+            let mut retry_attempt = 0u32;
+            let mut agent_error = None;
+            // The entry for this iteration if it stops the run before finishing
+            let early_entry = |retries: u32| {
+                progress_entry(
+                    iteration,
+                    iteration_start,
+                    prd_before.as_ref(),
+                    retries.min(config.max_retries),
+                    None,
+                )
+            };
+            let output = loop {
+                // Check circuit breaker
+                if config.circuit_breaker_threshold > 0
+                    && consecutive_failures >= config.circuit_breaker_threshold
+                {
+                    return self
+                        .stop_early(
+                            &config,
+                            &mut checkpoint,
+                            consecutive_failures,
+                            early_entry(retry_attempt),
+                            iteration,
+                            StopReason::CircuitBreakerTriggered {
+                                consecutive_failures,
+                            },
+                        )
+                        .await;
+                }
 
-            // Check for completion phrase in output
-            let phrase_detected = output.contains(&self.config.completion_phrase);
+                self.events.set_attempt(hook_retries + retry_attempt + 1);
+                match agent.run(&prompt, &self.events).await {
+                    Ok(output) => {
+                        consecutive_failures = 0; // Reset on success
+                        usage_limit_waits = 0;
+                        break output;
+                    }
+                    Err(
+                        e @ (Error::AgentErrorDetected {
+                            class: ErrorClass::Transient | ErrorClass::RateLimited,
+                            ..
+                        }
+                        | Error::AgentTimeout { .. }
+                        | Error::AgentIdleTimeout { .. }),
+                    ) => {
+                        agent_error = Some(e.to_string());
+                        usage_limit_waits = 0;
+                        retry_attempt += 1;
+                        consecutive_failures += 1;
 
-            // Re-read PRD after agent run to check if it made updates
-            let prd_complete_after = if let Some(prd_path) = &self.config.prd_path {
-                match Prd::load(prd_path) {
-                    Ok(prd) => {
-                        let completed = prd.stories.iter().filter(|s| s.passes).count();
-                        let total = prd.stories.len();
+                        if retry_attempt > config.max_retries {
+                            // Give up on this iteration, continue to next
+                            // (circuit breaker will catch persistent failures)
+                            break AgentOutput::empty();
+                        }
+
+                        let mut backoff = calculate_backoff(retry_attempt, &config);
+                        if matches!(
+                            e,
+                            Error::AgentErrorDetected {
+                                class: ErrorClass::RateLimited,
+                                ..
+                            }
+                        ) {
+                            // Rate limits need a longer wait than ordinary transient failures
+                            backoff = backoff.max(config.rate_limit_backoff_secs);
+                        }
                         let _ = self
                             .events
-                            .send(Event::PrdUpdated { completed, total })
+                            .send(Event::RetryScheduled {
+                                backoff_secs: backoff,
+                                attempt: retry_attempt,
+                                max_retries: config.max_retries,
+                            })
+                            .await;
+                        self.set_state(RunnerState::BackingOff);
+                        if !self
+                            .sleep_unless_stopped(Duration::from_secs(backoff))
+                            .await
+                        {
+                            return self
+                                .stop_early(
+                                    &config,
+                                    &mut checkpoint,
+                                    consecutive_failures,
+                                    early_entry(retry_attempt),
+                                    iteration,
+                                    StopReason::Cancelled,
+                                )
+                                .await;
+                        }
+                        self.set_state(RunnerState::Running);
+                    }
+                    Err(Error::UsageLimitReached { resume_at }) => {
+                        // Waiting out a quota is not a failure: retry the same
+                        // attempt once it resets, without touching the counters.
+                        // A limit that persists through every wait stops the run.
+                        if config.max_usage_limit_waits > 0
+                            && usage_limit_waits >= config.max_usage_limit_waits
+                        {
+                            return self
+                                .stop_early(
+                                    &config,
+                                    &mut checkpoint,
+                                    consecutive_failures,
+                                    early_entry(retry_attempt),
+                                    iteration,
+                                    StopReason::UsageLimitPersisted {
+                                        waits: usage_limit_waits,
+                                    },
+                                )
+                                .await;
+                        }
+                        usage_limit_waits += 1;
+                        let wait = (resume_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+                        self.set_state(RunnerState::Sleeping);
+                        if !self.sleep_unless_stopped(wait).await {
+                            return self
+                                .stop_early(
+                                    &config,
+                                    &mut checkpoint,
+                                    consecutive_failures,
+                                    early_entry(retry_attempt),
+                                    iteration,
+                                    StopReason::Cancelled,
+                                )
+                                .await;
+                        }
+                        self.set_state(RunnerState::Running);
+                    }
+                    Err(Error::Cancelled) => {
+                        return self
+                            .stop_early(
+                                &config,
+                                &mut checkpoint,
+                                consecutive_failures,
+                                early_entry(retry_attempt),
+                                iteration,
+                                StopReason::Cancelled,
+                            )
                             .await;
-                        prd.is_complete()
                     }
                     Err(e) => {
-                        let _ = self
-                            .events
-                            .send(Event::warning(format!(
-                                "failed to read PRD after agent: {}",
-                                e
-                            )))
+                        // Other errors (AgentNotFound, fatal error patterns, etc.) - don't retry
+                        let message = format!("agent failed: {}", e);
+                        return self
+                            .stop_early(
+                                &config,
+                                &mut checkpoint,
+                                consecutive_failures,
+                                early_entry(retry_attempt),
+                                iteration,
+                                StopReason::FatalError { message },
+                            )
                             .await;
-                        false
                     }
                 }
+            };
+
+            if config.iteration_feedback {
+                previous = Some(IterationFeedback {
+                    iteration,
+                    exit_code: output.exit_code,
+                    error: agent_error.clone(),
+                    retries: retry_attempt.min(config.max_retries),
+                    last_lines: output
+                        .last_lines(config.feedback_output_lines)
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                });
+            }
+            last_error = agent_error;
+
+            let mut post_env = hook_env;
+            post_env.push((
+                "WIGGLE_PUPPY_HOOK_STAGE".to_string(),
+                HookStage::PostIteration.to_string(),
+            ));
+            post_env.push((
+                "WIGGLE_PUPPY_EXIT_CODE".to_string(),
+                output.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+            ));
+            match self
+                .run_hooks(
+                    &config,
+                    HookStage::PostIteration,
+                    &config.post_iteration_hooks,
+                    &post_env,
+                    &mut hook_retries,
+                )
+                .await
+            {
+                HookStep::Continue => hook_retries = 0,
+                HookStep::Retry => {
+                    iteration -= 1;
+                    continue;
+                }
+                HookStep::Stop(reason) => {
+                    let entry = progress_entry(
+                        iteration,
+                        iteration_start,
+                        prd_before.as_ref(),
+                        retry_attempt.min(config.max_retries),
+                        Some(&output),
+                    );
+                    return self
+                        .stop_early(
+                            &config,
+                            &mut checkpoint,
+                            consecutive_failures,
+                            entry,
+                            iteration,
+                            reason,
+                        )
+                        .await;
+                }
+            }
+
+            // Check whether the agent signalled completion, through the
+            // configured detectors or else the completion phrase on a line
+            // by itself (so an echoed prompt doesn't count)
+            let signal = if config.completion_detectors.is_empty() {
+                output
+                    .contains_line(&completion_phrase)
+                    .then_some(CompletionReason::CompletionPhraseDetected)
             } else {
-                false
+                completion::detect(&mut config.completion_detectors, &output)
+                    .map(|detector| CompletionReason::DetectorMatched { detector })
             };
+            let signalled = signal.is_some();
+
+            // Re-read PRD after agent run to check if it made updates
+            let prd_after = self
+                .load_prd(&config, "failed to read PRD after agent")
+                .await;
+
+            // Report story-level changes the agent made to the PRD
+            let mut completed_stories = Vec::new();
+            if let (Some(before), Some(after)) = (&prd_before, &prd_after) {
+                for change in before.diff(after) {
+                    if let StoryChange::Completed { id, title } = &change {
+                        completed_stories.push(StoryRef {
+                            id: id.clone(),
+                            title: title.clone(),
+                        });
+                    }
+                    let _ = self.events.send(change.into()).await;
+                }
+            }
+            // A configured PRD that failed to load counts as incomplete
+            let prd_complete_after = config
+                .prd_path
+                .is_some()
+                .then(|| prd_after.as_ref().is_some_and(Prd::is_complete));
 
-            // Determine completion status
-            let completion_detected = phrase_detected || prd_complete_after;
+            // Determine completion status under the completion policy
+            let reason = config.completion_policy.resolve(signal, prd_complete_after);
+            let completion_detected = reason.is_some();
 
             let _ = self
                 .events
@@ -380,64 +1464,113 @@ impl Runner {
                 })
                 .await;
 
+            let entry = ProgressEntry {
+                completed: completed_stories,
+                ..progress_entry(
+                    iteration,
+                    iteration_start,
+                    prd_before.as_ref(),
+                    retry_attempt.min(config.max_retries),
+                    Some(&output),
+                )
+            };
+            self.record_progress(&config, &entry).await;
+
+            checkpoint.update(iteration, consecutive_failures);
+            self.save_checkpoint(&config, &checkpoint).await;
+
+            // The agent claimed to be done while stories are still pending
+            if signalled && reason.is_none() && prd_complete_after == Some(false) {
+                let pending = prd_after
+                    .as_ref()
+                    .map(|prd| prd.stories.iter().filter(|s| !s.passes).count());
+                let message = match pending {
+                    Some(pending) => format!(
+                        "agent signalled completion but {} stor{} still pending; continuing",
+                        pending,
+                        if pending == 1 { "y is" } else { "ies are" }
+                    ),
+                    None => "agent signalled completion but the PRD could not be read; continuing"
+                        .to_string(),
+                };
+                let _ = self.events.send(Event::warning(message)).await;
+            }
+
             // Check completion conditions
-            if phrase_detected && prd_complete_after {
-                let _ = self
-                    .events
-                    .send(Event::Completed {
-                        iterations: iteration,
-                        reason: CompletionReason::Both,
-                    })
-                    .await;
-                return Ok(Outcome::Completed {
-                    iterations: iteration,
-                    reason: CompletionReason::Both,
-                });
-            } else if prd_complete_after {
-                let _ = self
-                    .events
-                    .send(Event::Completed {
-                        iterations: iteration,
-                        reason: CompletionReason::AllStoriesComplete,
-                    })
-                    .await;
-                return Ok(Outcome::Completed {
-                    iterations: iteration,
-                    reason: CompletionReason::AllStoriesComplete,
-                });
-            } else if phrase_detected {
-                let _ = self
-                    .events
-                    .send(Event::Completed {
-                        iterations: iteration,
-                        reason: CompletionReason::CompletionPhraseDetected,
-                    })
-                    .await;
-                return Ok(Outcome::Completed {
+            if let Some(reason) = reason {
+                match self.verify_completion(&config, &mut verify_feedback).await {
+                    Verdict::Complete => {
+                        let outcome = Outcome::Completed {
+                            iterations: iteration,
+                            reason,
+                        };
+                        return self.finish(&config, &mut checkpoint, outcome).await;
+                    }
+                    Verdict::Continue => {}
+                    // This iteration is already in the progress log
+                    Verdict::Stop(reason) => return self.stop(iteration, reason).await,
+                }
+            }
+
+            // Stop if the agent says it is stuck or needs an answer
+            if let Some(reason) = completion::agent_stop_signal(&output) {
+                let outcome = Outcome::Stopped {
                     iterations: iteration,
-                    reason: CompletionReason::CompletionPhraseDetected,
-                });
+                    reason,
+                };
+                return self.finish(&config, &mut checkpoint, outcome).await;
+            }
+
+            // Stop if the agent keeps going without making progress
+            if let Some(detector) = &mut stall {
+                let completed = |prd: &Prd| prd.stories.iter().filter(|s| s.passes).count();
+                let stalled_for = detector.observe(
+                    prd_before.as_ref().map(completed),
+                    prd_after.as_ref().map(completed),
+                    &output.combined,
+                );
+                if stalled_for >= config.stall_threshold {
+                    let _ = self
+                        .events
+                        .send(Event::warning(format!(
+                            "no progress in {} consecutive iterations; stopping",
+                            stalled_for
+                        )))
+                        .await;
+                    return self
+                        .stop(
+                            iteration,
+                            StopReason::Stalled {
+                                iterations_without_progress: stalled_for,
+                            },
+                        )
+                        .await;
+                } else if stalled_for + 1 == config.stall_threshold {
+                    let _ = self
+                        .events
+                        .send(Event::warning(format!(
+                            "no progress in {} consecutive iterations; stopping if the next one makes none",
+                            stalled_for
+                        )))
+                        .await;
+                }
             }
 
-            // Delay before next iteration
-            if !self.config.delay.is_zero() {
-                tokio::time::sleep(self.config.delay).await;
+            // Delay before next iteration; a stop request cuts it short
+            if !config.delay.is_zero() {
+                self.set_state(RunnerState::Sleeping);
+                tokio::select! {
+                    _ = tokio::time::sleep(config.delay) => {}
+                    _ = self.cancel.cancelled() => {}
+                    _ = self.stop_requested.cancelled() => {}
+                }
             }
 
             // Check cancellation after delay
-            if self.is_cancelled() {
-                let _ = self
-                    .events
-                    .send(Event::Stopped {
-                        iterations: iteration,
-                        reason: StopReason::Cancelled,
-                    })
-                    .await;
-                return Ok(Outcome::Stopped {
-                    iterations: iteration,
-                    reason: StopReason::Cancelled,
-                });
+            if self.should_stop() {
+                return self.stop(iteration, StopReason::Cancelled).await;
             }
+            self.set_state(RunnerState::Running);
         }
     }
 }
@@ -445,13 +1578,15 @@ impl Runner {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::event::EventEnvelope;
     use std::time::Duration;
 
     #[test]
     fn test_runner_handle_cancel() {
-        let cancel_flag = Arc::new(AtomicBool::new(false));
         let handle = RunnerHandle {
-            cancel_flag: cancel_flag.clone(),
+            cancel: CancellationToken::new(),
+            stop_requested: CancellationToken::new(),
+            control: Arc::new(Control::default()),
         };
 
         assert!(!handle.is_cancelled());
@@ -461,8 +1596,11 @@ mod tests {
 
     #[test]
     fn test_runner_handle_clone() {
-        let cancel_flag = Arc::new(AtomicBool::new(false));
-        let handle1 = RunnerHandle { cancel_flag };
+        let handle1 = RunnerHandle {
+            cancel: CancellationToken::new(),
+            stop_requested: CancellationToken::new(),
+            control: Arc::new(Control::default()),
+        };
         let handle2 = handle1.clone();
 
         handle1.cancel();
@@ -529,28 +1667,68 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_runner_max_iterations() {
+    async fn test_runner_stop_after_iteration_lets_agent_finish() {
         let config = Config::new()
-            .agent_command("echo")
-            .agent_args(vec![])
+            .agent_command("sh")
+            .agent_args(vec!["-c".to_string(), "sleep 0.3; echo done".to_string()])
+            .prompt_delivery(crate::agent::PromptDelivery::Stdin)
             .prompt_text("test")
-            .max_iterations(0)
-            .delay(Duration::ZERO);
-        let (runner, _rx, _handle) = Runner::new(config);
+            .max_iterations(10)
+            .delay(Duration::from_secs(60))
+            .auto_completion_instruction(false);
+        let (runner, mut rx, handle) = Runner::new(config);
+
+        // Request the stop while the first agent run is still in progress
+        let watcher = tokio::spawn(async move {
+            let mut exit_codes = Vec::new();
+            while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+                match event {
+                    Event::IterationStarted { .. } => handle.stop_after_iteration(),
+                    Event::AgentFinished { exit_code, .. } => exit_codes.push(exit_code),
+                    _ => {}
+                }
+            }
+            exit_codes
+        });
 
-        let outcome = runner.run().await.expect("should return outcome");
+        let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("stop request should skip the delay")
+            .expect("should return outcome");
         assert!(matches!(
             outcome,
             Outcome::Stopped {
-                iterations: 0,
-                reason: StopReason::MaxIterations,
+                iterations: 1,
+                reason: StopReason::Cancelled,
             }
         ));
+
+        drop(runner);
+        let exit_codes = watcher.await.expect("watcher should finish");
+        assert_eq!(exit_codes, vec![Some(0)], "agent should run to completion");
+    }
+
+    #[test]
+    fn test_runner_handle_pause_resume() {
+        let (_runner, _rx, handle) = Runner::new(Config::new().prompt_text("test"));
+        assert_eq!(handle.state(), RunnerState::Idle);
+        assert!(!handle.is_paused());
+
+        handle.pause();
+        assert!(handle.is_paused());
+        handle.resume();
+        assert!(!handle.is_paused());
+
+        // A suspended agent reports as paused while the iteration runs
+        handle.control.state.send_replace(RunnerState::Running);
+        handle.suspend();
+        assert_eq!(handle.state(), RunnerState::Paused);
+        handle.resume();
+        assert_eq!(handle.state(), RunnerState::Running);
     }
 
     #[tokio::test]
-    async fn test_runner_completion_phrase_detected() {
-        // Use echo to output the completion phrase
+    async fn test_runner_pause_between_iterations() {
         let config = Config::new()
             .agent_command("echo")
             .agent_args(vec![])
@@ -559,26 +1737,798 @@ mod tests {
             .max_iterations(5)
             .delay(Duration::ZERO)
             .auto_completion_instruction(false);
-        let (runner, _rx, _handle) = Runner::new(config);
+        let (runner, mut rx, handle) = Runner::new(config);
+
+        // Paused before the first iteration; resume once the pause is observed
+        handle.pause();
+        let watcher = tokio::spawn(async move {
+            let mut states = Vec::new();
+            while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+                match event {
+                    Event::Paused { agent_suspended } => {
+                        assert!(!agent_suspended);
+                        states.push(handle.state());
+                        handle.resume();
+                    }
+                    Event::Resumed { .. } => states.push(handle.state()),
+                    Event::IterationStarted { .. } => states.push(handle.state()),
+                    _ => {}
+                }
+            }
+            states.push(handle.state());
+            states
+        });
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("resume should unblock the runner")
+            .expect("should return outcome");
+        assert!(outcome.is_completed());
+
+        drop(runner);
+        let states = watcher.await.expect("watcher should finish");
+        assert_eq!(
+            states,
+            vec![
+                RunnerState::Paused,
+                RunnerState::Running,
+                RunnerState::Running,
+                RunnerState::Finished,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runner_live_reconfiguration() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("still working")
+            .max_iterations(1)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, handle) = Runner::new(config);
+
+        // Extend the run during the first iteration; the unchanged delay is not reported
+        let watcher = tokio::spawn(async move {
+            let mut changes = Vec::new();
+            while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+                match event {
+                    Event::IterationStarted { iteration: 1, .. } => {
+                        handle.set_max_iterations(2);
+                        handle.set_delay(Duration::ZERO);
+                    }
+                    Event::ConfigChanged { changes: c } => changes.extend(c),
+                    _ => {}
+                }
+            }
+            changes
+        });
 
         let outcome = runner.run().await.expect("should return outcome");
         assert!(matches!(
             outcome,
-            Outcome::Completed {
-                iterations: 1,
-                reason: CompletionReason::CompletionPhraseDetected,
+            Outcome::Stopped {
+                iterations: 2,
+                reason: StopReason::MaxIterations,
             }
         ));
+
+        drop(runner);
+        let changes = watcher.await.expect("watcher should finish");
+        assert_eq!(changes, vec![ConfigChange::new("max_iterations", 1, 2)]);
     }
 
     #[tokio::test]
-    async fn test_runner_events_emitted() {
+    async fn test_runner_live_completion_phrase_change() {
         let config = Config::new()
             .agent_command("echo")
             .agent_args(vec![])
-            .prompt_text("<promise>COMPLETE</promise>")
+            .prompt_text("ALL DONE")
             .completion_phrase("<promise>COMPLETE</promise>")
-            .max_iterations(5)
+            .max_iterations(3)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, handle) = Runner::new(config);
+
+        // Changed before the first iteration, so it applies from the start
+        handle.set_completion_phrase("ALL DONE");
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Completed {
+                iterations: 1,
+                reason: CompletionReason::CompletionPhraseDetected,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_writes_progress_log() {
+        let path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_runner_progress_{}.txt",
+            std::process::id()
+        ));
+        std::fs::remove_file(&path).ok();
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("still working")
+            .progress_path(&path)
+            .max_iterations(2)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(outcome.is_stopped());
+
+        let content = std::fs::read_to_string(&path).expect("progress log should exist");
+        assert!(content.contains("] Iteration 1\n"));
+        assert!(content.contains("] Iteration 2\n"));
+        assert!(content.contains("exit code: 0, retries: 0"));
+        assert!(content.contains("    still working\n"));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_progress_log_records_stopping_iteration() {
+        use crate::pattern::ErrorPattern;
+
+        let path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_runner_progress_stop_{}.txt",
+            std::process::id()
+        ));
+        std::fs::remove_file(&path).ok();
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("Invalid API key")
+            .error_patterns(vec![ErrorPattern::literal(
+                "Invalid API key",
+                ErrorClass::Fatal,
+            )])
+            .progress_path(&path)
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(outcome.is_stopped());
+
+        let content = std::fs::read_to_string(&path).expect("progress log should exist");
+        assert!(content.contains("] Iteration 1\n"));
+        assert!(content.contains("  Stopped: fatal error: agent failed: "));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_max_iterations() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("test")
+            .max_iterations(0)
+            .delay(Duration::ZERO);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 0,
+                reason: StopReason::MaxIterations,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_completion_phrase_detected() {
+        // Use echo to output the completion phrase
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .completion_phrase("<promise>COMPLETE</promise>")
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Completed {
+                iterations: 1,
+                reason: CompletionReason::CompletionPhraseDetected,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_echoed_instruction_does_not_complete() {
+        // An agent that echoes its prompt quotes the completion phrase
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("Fix the bug")
+            .max_iterations(2)
+            .delay(Duration::ZERO);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 2,
+                reason: StopReason::MaxIterations,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_agent_stop_signals() {
+        for (prompt, expected) in [
+            (
+                "<promise>BLOCKED: missing API token</promise>",
+                StopReason::AgentBlocked {
+                    reason: "missing API token".to_string(),
+                },
+            ),
+            (
+                "<promise>NEEDS_INPUT: which database?</promise>",
+                StopReason::AwaitingInput {
+                    question: "which database?".to_string(),
+                },
+            ),
+        ] {
+            let config = Config::new()
+                .agent_command("echo")
+                .agent_args(vec![])
+                .prompt_text(prompt)
+                .max_iterations(5)
+                .delay(Duration::ZERO)
+                .auto_completion_instruction(false);
+            let (runner, _rx, _handle) = Runner::new(config);
+
+            let outcome = runner.run().await.expect("should return outcome");
+            match outcome {
+                Outcome::Stopped { iterations, reason } => {
+                    assert_eq!(iterations, 1);
+                    assert_eq!(reason, expected);
+                }
+                other => panic!("expected the agent to stop the run, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runner_completion_nonce() {
+        // Prints the plain phrase on its own line: not this run's phrase
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .completion_nonce(true)
+            .max_iterations(1)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(!outcome.is_completed());
+
+        // Prints the nonced phrase it was given in the instruction
+        let config = Config::new()
+            .agent_command("sh")
+            .agent_args(vec![
+                "-c".to_string(),
+                "echo \"$0\" | grep -o '<promise>COMPLETE-[0-9a-f]*</promise>'".to_string(),
+            ])
+            .prompt_text("Fix the bug")
+            .completion_nonce(true)
+            .max_iterations(1)
+            .delay(Duration::ZERO);
+        let (runner, _rx, _handle) = Runner::new(config);
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Completed {
+                iterations: 1,
+                reason: CompletionReason::CompletionPhraseDetected,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_verify_failure_feeds_next_prompt() {
+        let marker =
+            std::env::temp_dir().join(format!("wiggle_puppy_test_verify_{}", std::process::id()));
+        std::fs::remove_file(&marker).ok();
+
+        // Fails the first time, passes once the marker exists
+        let verify = format!(
+            "test -f {0} || {{ touch {0}; echo 'error: 2 tests failed'; exit 1; }}",
+            marker.display()
+        );
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .completion_phrase("<promise>COMPLETE</promise>")
+            .verify_command(verify)
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Completed {
+                iterations: 2,
+                reason: CompletionReason::CompletionPhraseDetected,
+            }
+        ));
+
+        drop(runner);
+        let mut verify_results = Vec::new();
+        let mut agent_output = String::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            match event {
+                Event::VerifyFinished {
+                    exit_code,
+                    success,
+                    output_tail,
+                    ..
+                } => verify_results.push((exit_code, success, output_tail)),
+                Event::AgentOutput { text, .. } => {
+                    agent_output.push_str(&text);
+                    agent_output.push('\n');
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(verify_results.len(), 2);
+        assert_eq!(
+            verify_results[0],
+            (Some(1), false, "error: 2 tests failed".to_string())
+        );
+        assert_eq!(verify_results[1], (Some(0), true, String::new()));
+
+        // The echo agent repeats its prompt, so the second run shows the feedback
+        assert_eq!(agent_output.matches("## Verification failed").count(), 1);
+        assert!(agent_output.contains("exited with code 1"));
+        assert!(agent_output.contains("error: 2 tests failed"));
+
+        std::fs::remove_file(&marker).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_verify_gates_complete_prd() {
+        let prd_path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_verify_prd_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &prd_path,
+            r#"{"name": "Test", "branchName": "test", "description": "", "stories": [
+                {"id": "1", "title": "Done", "description": "", "priority": 1, "passes": true,
+                 "acceptance_criteria": [], "depends_on": []}]}"#,
+        )
+        .expect("should write PRD");
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("still broken")
+            .prd_path(&prd_path)
+            .verify_command("exit 1")
+            .max_iterations(2)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        // Verify never passes, so the complete PRD is never accepted
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 2,
+                reason: StopReason::MaxIterations,
+            }
+        ));
+
+        std::fs::remove_file(&prd_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_policy_all_ignores_premature_phrase() {
+        let prd_path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_policy_prd_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &prd_path,
+            r#"{"name": "Test", "branchName": "test", "description": "", "stories": [
+                {"id": "1", "title": "Pending", "description": "", "priority": 1, "passes": false,
+                 "acceptance_criteria": [], "depends_on": []}]}"#,
+        )
+        .expect("should write PRD");
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .prd_path(&prd_path)
+            .completion_policy(CompletionPolicy::All)
+            .max_iterations(1)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::MaxIterations,
+            }
+        ));
+
+        drop(runner);
+        let mut warnings = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::Warning { message } = event {
+                warnings.push(message);
+            }
+        }
+        assert_eq!(
+            warnings,
+            vec!["agent signalled completion but 1 story is still pending; continuing"]
+        );
+
+        std::fs::remove_file(&prd_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_renders_prompt_template() {
+        let prd_path = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_template_prd_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &prd_path,
+            r#"{"name": "Test", "branchName": "test", "description": "", "stories": [
+                {"id": "1", "title": "Setup", "description": "", "priority": 1, "passes": true,
+                 "acceptance_criteria": [], "depends_on": []},
+                {"id": "2", "title": "Login", "description": "", "priority": 2, "passes": false,
+                 "acceptance_criteria": ["form validates"], "depends_on": []}]}"#,
+        )
+        .expect("should write PRD");
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text(
+                "{{iteration}}/{{max_iterations}} {{next_story.id}} {{next_story.title}} \
+                 {{prd.completed}}/{{prd.total}} {{team}} [{{last_error}}]",
+            )
+            .template_var("team", "web")
+            .template_var("iteration", "ignored")
+            .prd_path(&prd_path)
+            .max_iterations(2)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        runner.run().await.expect("should return outcome");
+        drop(runner);
+        let mut lines = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::AgentOutput { text, .. } = event {
+                lines.push(text);
+            }
+        }
+        assert_eq!(
+            lines,
+            vec!["1/2 2 Login 1/2 web []", "2/2 2 Login 1/2 web []"]
+        );
+
+        std::fs::remove_file(&prd_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_iteration_feedback() {
+        // Echoes its prompt, then fails
+        let config = Config::new()
+            .agent_command("sh")
+            .agent_args(vec!["-c".to_string(), "echo \"$0\"; exit 3".to_string()])
+            .prompt_text("Fix the build")
+            .iteration_feedback(true)
+            .feedback_output_lines(1)
+            .max_iterations(2)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        runner.run().await.expect("should return outcome");
+        drop(runner);
+        let mut lines = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::AgentOutput { text, .. } = event {
+                lines.push(text);
+            }
+        }
+        assert_eq!(
+            lines,
+            vec![
+                "Fix the build",
+                "Fix the build",
+                "",
+                "## Previous iteration",
+                "",
+                "- Iteration: 1",
+                "- Exit code: 3",
+                "- Retries: 0",
+                "",
+                "Last 1 line of output:",
+                "",
+                "```",
+                "Fix the build",
+                "```",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runner_unknown_template_variable_is_fatal() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("Work on {{story}}")
+            .max_iterations(2)
+            .delay(Duration::ZERO);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::FatalError { ref message },
+            } if message.contains("unknown variable 'story'")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_policy_prd_only_requires_prd() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .completion_policy(CompletionPolicy::PrdOnly);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let result = runner.run().await;
+        assert!(matches!(result, Err(Error::ConfigError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_runner_hooks_receive_iteration_env() {
+        let log = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_hooks_{}.log",
+            std::process::id()
+        ));
+        std::fs::remove_file(&log).ok();
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("working")
+            .add_pre_iteration_hook(Hook::new(format!(
+                "echo \"$WIGGLE_PUPPY_HOOK_STAGE $WIGGLE_PUPPY_ITERATION/$WIGGLE_PUPPY_MAX_ITERATIONS\" >> {}",
+                log.display()
+            )))
+            .add_post_iteration_hook(Hook::new(format!(
+                "echo \"$WIGGLE_PUPPY_HOOK_STAGE $WIGGLE_PUPPY_ITERATION exit=$WIGGLE_PUPPY_EXIT_CODE\" >> {}",
+                log.display()
+            )))
+            .max_iterations(2)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 2,
+                reason: StopReason::MaxIterations,
+            }
+        ));
+
+        let content = std::fs::read_to_string(&log).expect("hooks should write the log");
+        assert_eq!(
+            content.lines().collect::<Vec<_>>(),
+            vec![
+                "pre-iteration 1/2",
+                "post-iteration 1 exit=0",
+                "pre-iteration 2/2",
+                "post-iteration 2 exit=0",
+            ]
+        );
+
+        std::fs::remove_file(&log).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_hook_failure_retries_iteration() {
+        let marker = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_hook_retry_{}",
+            std::process::id()
+        ));
+        std::fs::remove_file(&marker).ok();
+
+        // Fails the first time, passes once the marker exists
+        let hook = Hook::new(format!(
+            "test -f {0} || {{ touch {0}; exit 1; }}",
+            marker.display()
+        ))
+        .on_failure(HookFailure::RetryIteration);
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .add_pre_iteration_hook(hook)
+            .initial_backoff_secs(0)
+            .max_iterations(1)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        // The retried iteration keeps its number, so a single iteration completes
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Completed {
+                iterations: 1,
+                reason: CompletionReason::CompletionPhraseDetected,
+            }
+        ));
+
+        drop(runner);
+        let mut retries = 0;
+        let mut agent_runs = 0;
+        while let Some(envelope) = rx.recv().await {
+            match envelope.event {
+                Event::RetryScheduled { attempt: 1, .. } => retries += 1,
+                Event::AgentFinished { .. } => {
+                    // The agent ran on the iteration's second attempt
+                    assert_eq!((envelope.iteration, envelope.attempt), (1, 2));
+                    agent_runs += 1;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(retries, 1);
+        assert_eq!(agent_runs, 1);
+
+        std::fs::remove_file(&marker).ok();
+    }
+
+    #[tokio::test]
+    async fn test_runner_hook_failure_stops_run() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .add_post_iteration_hook(Hook::new("exit 7").on_failure(HookFailure::Stop))
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        match outcome {
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::FatalError { message },
+            } => assert_eq!(
+                message,
+                "post-iteration hook 'exit 7' failed with exit code 7"
+            ),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runner_stops_when_stalled() {
+        // The echo agent repeats the same output and changes nothing
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("still thinking")
+            .stall_threshold(3)
+            .max_iterations(10)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        // The first iteration's new output counts as progress
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 4,
+                reason: StopReason::Stalled {
+                    iterations_without_progress: 3,
+                },
+            }
+        ));
+
+        drop(runner);
+        let mut warnings = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::Warning { message } = event {
+                warnings.push(message);
+            }
+        }
+        assert_eq!(
+            warnings,
+            vec![
+                "no progress in 2 consecutive iterations; stopping if the next one makes none",
+                "no progress in 3 consecutive iterations; stopping",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runner_completion_detectors_replace_phrase() {
+        use crate::completion::{ConsecutiveDetector, SentinelLineDetector};
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("DONE")
+            .completion_phrase("DONE")
+            .add_completion_detector(ConsecutiveDetector::new(
+                SentinelLineDetector::new("DONE"),
+                2,
+            ))
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, _handle) = Runner::new(config);
+
+        // The phrase alone would complete on iteration 1
+        let outcome = runner.run().await.expect("should return outcome");
+        match outcome {
+            Outcome::Completed {
+                iterations: 2,
+                reason: CompletionReason::DetectorMatched { detector },
+            } => assert_eq!(
+                detector,
+                "sentinel line \"DONE\" for 2 consecutive iterations"
+            ),
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runner_events_emitted() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("<promise>COMPLETE</promise>")
+            .completion_phrase("<promise>COMPLETE</promise>")
+            .max_iterations(5)
             .delay(Duration::ZERO)
             .auto_completion_instruction(false);
         let (runner, mut rx, _handle) = Runner::new(config);
@@ -589,7 +2539,7 @@ mod tests {
         // Collect all events
         drop(runner); // Drop to close the sender
         let mut events = Vec::new();
-        while let Some(event) = rx.recv().await {
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
             events.push(event);
         }
 
@@ -611,6 +2561,49 @@ mod tests {
         assert!(events.iter().any(|e| matches!(e, Event::Completed { .. })));
     }
 
+    #[tokio::test]
+    async fn test_runner_event_envelopes() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("working")
+            .max_iterations(2)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+        let run_id = runner.run_id();
+
+        runner.run().await.expect("should return outcome");
+        drop(runner);
+        let mut envelopes = Vec::new();
+        while let Some(envelope) = rx.recv().await {
+            envelopes.push(envelope);
+        }
+
+        for (i, envelope) in envelopes.iter().enumerate() {
+            assert_eq!(envelope.seq, i as u64 + 1);
+            assert_eq!(envelope.run_id, run_id);
+            if i > 0 {
+                assert!(envelope.timestamp >= envelopes[i - 1].timestamp);
+            }
+            match &envelope.event {
+                Event::Started { .. } => assert_eq!((envelope.iteration, envelope.attempt), (0, 0)),
+                Event::IterationStarted { iteration, .. } => {
+                    assert_eq!((envelope.iteration, envelope.attempt), (*iteration, 1))
+                }
+                _ => {}
+            }
+        }
+        assert!(matches!(
+            envelopes.last(),
+            Some(EventEnvelope {
+                iteration: 2,
+                event: Event::Stopped { .. },
+                ..
+            })
+        ));
+    }
+
     #[tokio::test]
     async fn test_runner_no_prompt_error() {
         let config = Config::new().max_iterations(5);
@@ -626,6 +2619,42 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_runner_stops_when_usage_limit_persists() {
+        // An old usage limit line in the output must not cause a busy loop
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("Claude AI usage limit reached|1000000000")
+            .usage_limit_wait_secs(0)
+            .max_usage_limit_waits(2)
+            .max_iterations(3)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        let started = Utc::now();
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::UsageLimitPersisted { waits: 2 },
+            }
+        ));
+
+        drop(runner);
+        let mut resume_times = Vec::new();
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            if let Event::RateLimited { resume_at } = event {
+                resume_times.push(resume_at);
+            }
+        }
+        assert_eq!(resume_times.len(), 3);
+        // The past reset time was replaced by the fallback wait
+        assert!(resume_times.iter().all(|resume_at| *resume_at >= started));
+    }
+
     #[tokio::test]
     async fn test_runner_agent_not_found() {
         let config = Config::new()
@@ -644,6 +2673,192 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_runner_fatal_error_pattern_stops_without_retry() {
+        use crate::pattern::ErrorPattern;
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("Invalid API key")
+            .error_patterns(vec![ErrorPattern::literal(
+                "Invalid API key",
+                ErrorClass::Fatal,
+            )])
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, _handle) = Runner::new(config);
+
+        let outcome = runner.run().await.expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::FatalError { .. },
+            }
+        ));
+
+        drop(runner);
+        while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+            assert!(!matches!(event, Event::RetryScheduled { .. }));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runner_cancel_interrupts_backoff() {
+        use crate::pattern::ErrorPattern;
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("connection reset")
+            .error_patterns(vec![ErrorPattern::literal(
+                "connection reset",
+                ErrorClass::Transient,
+            )])
+            .initial_backoff_secs(60)
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, handle) = Runner::new(config);
+
+        // Cancel as soon as the retry is scheduled
+        tokio::spawn(async move {
+            while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+                if matches!(event, Event::RetryScheduled { .. }) {
+                    handle.cancel();
+                }
+            }
+        });
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("cancel should interrupt the backoff sleep")
+            .expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::Cancelled,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_stop_request_interrupts_usage_limit_sleep() {
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("You've hit your limit")
+            .usage_limit_wait_secs(3600)
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, handle) = Runner::new(config);
+
+        // Ask to stop after the iteration once the runner starts sleeping
+        tokio::spawn(async move {
+            while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+                if matches!(event, Event::RateLimited { .. }) {
+                    handle.stop_after_iteration();
+                }
+            }
+        });
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("a stop request should interrupt the usage limit sleep")
+            .expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::Cancelled,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runner_stop_request_prevents_retry() {
+        use crate::pattern::ErrorPattern;
+
+        let config = Config::new()
+            .agent_command("echo")
+            .agent_args(vec![])
+            .prompt_text("connection reset")
+            .error_patterns(vec![ErrorPattern::literal(
+                "connection reset",
+                ErrorClass::Transient,
+            )])
+            .initial_backoff_secs(60)
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, mut rx, handle) = Runner::new(config);
+
+        // Ask to stop while the failing agent runs; its error would normally be retried
+        let events = tokio::spawn(async move {
+            let mut agent_runs = 0;
+            while let Some(EventEnvelope { event, .. }) = rx.recv().await {
+                match event {
+                    Event::AgentErrorDetected { .. } => {
+                        agent_runs += 1;
+                        handle.stop_after_iteration();
+                    }
+                    Event::AgentFinished { .. } => agent_runs += 1,
+                    _ => {}
+                }
+            }
+            agent_runs
+        });
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("a stop request should skip the backoff")
+            .expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::Cancelled,
+            }
+        ));
+
+        drop(runner);
+        assert_eq!(events.await.unwrap(), 1, "the agent should not be retried");
+    }
+
+    #[tokio::test]
+    async fn test_runner_cancel_interrupts_agent() {
+        let config = Config::new()
+            .agent_command("sleep")
+            .agent_args(vec!["30".to_string()])
+            .prompt_delivery(crate::agent::PromptDelivery::Stdin)
+            .prompt_text("test")
+            .max_iterations(5)
+            .delay(Duration::ZERO)
+            .auto_completion_instruction(false);
+        let (runner, _rx, handle) = Runner::new(config);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            handle.cancel();
+        });
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+            .await
+            .expect("cancel should interrupt the agent")
+            .expect("should return outcome");
+        assert!(matches!(
+            outcome,
+            Outcome::Stopped {
+                iterations: 1,
+                reason: StopReason::Cancelled,
+            }
+        ));
+    }
+
     #[test]
     fn test_calculate_backoff_first_attempt() {
         let config = Config::new()
@@ -0,0 +1,275 @@
+//! Stall detection for runs that stop making progress.
+//!
+//! This module provides `StallDetector`, which the runner feeds after every
+//! iteration. An iteration makes progress if it raises the PRD's
+//! completed-story count, or if it changes files in the workspace without
+//! repeating the previous iteration's output almost verbatim. When no
+//! workspace is tracked, any output that isn't a near-repeat counts as
+//! progress.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Directories never included in the workspace fingerprint.
+const SKIPPED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+
+/// Line-set similarity at or above which two outputs count as a repeat.
+const REPEAT_SIMILARITY: f64 = 0.9;
+
+/// Tracks iterations that made no progress.
+#[derive(Debug)]
+pub(crate) struct StallDetector {
+    /// Workspace root (canonicalized), if file changes are tracked.
+    workspace: Option<PathBuf>,
+    /// Files the runner itself rewrites, left out of the fingerprint.
+    excluded: Vec<PathBuf>,
+    /// Fingerprint of the workspace after the last iteration.
+    fingerprint: Option<u64>,
+    /// Highest PRD completed-story count seen so far.
+    best_completed: Option<usize>,
+    /// Normalized lines of the previous iteration's output.
+    last_output: Option<HashSet<String>>,
+    /// Consecutive iterations without progress.
+    iterations_without_progress: u32,
+}
+
+impl StallDetector {
+    /// Create a detector, fingerprinting the workspace (if any) as a baseline.
+    ///
+    /// Files in `excluded`, such as the progress log and checkpoint, are
+    /// ignored since the runner writes them every iteration.
+    pub(crate) fn new(workspace: Option<&Path>, excluded: &[&Path]) -> Self {
+        let workspace = workspace.map(|path| path.canonicalize().unwrap_or(path.to_path_buf()));
+        let excluded = excluded
+            .iter()
+            .map(|path| canonicalize_file(path))
+            .collect();
+        let mut detector = Self {
+            workspace,
+            excluded,
+            fingerprint: None,
+            best_completed: None,
+            last_output: None,
+            iterations_without_progress: 0,
+        };
+        detector.fingerprint = detector.fingerprint_workspace();
+        detector
+    }
+
+    /// Record a finished iteration.
+    ///
+    /// Takes the PRD completed-story counts before and after the agent ran
+    /// (if a PRD is configured) and the agent's output. Returns the number of
+    /// consecutive iterations without progress, including this one.
+    pub(crate) fn observe(
+        &mut self,
+        completed_before: Option<usize>,
+        completed_after: Option<usize>,
+        output: &str,
+    ) -> u32 {
+        let best = self.best_completed.max(completed_before);
+        let prd_progress = match (best, completed_after) {
+            (Some(best), Some(after)) => after > best,
+            (None, Some(after)) => after > 0,
+            _ => false,
+        };
+        self.best_completed = best.max(completed_after);
+
+        let fingerprint = self.fingerprint_workspace();
+        let files_changed = self.workspace.is_none() || fingerprint != self.fingerprint;
+        self.fingerprint = fingerprint;
+
+        let lines = normalize(output);
+        let repeated = self
+            .last_output
+            .as_ref()
+            .is_some_and(|last| similarity(last, &lines) >= REPEAT_SIMILARITY);
+        self.last_output = Some(lines);
+
+        if prd_progress || (files_changed && !repeated) {
+            self.iterations_without_progress = 0;
+        } else {
+            self.iterations_without_progress += 1;
+        }
+        self.iterations_without_progress
+    }
+
+    /// Hash the path, size and modification time of every workspace file.
+    fn fingerprint_workspace(&self) -> Option<u64> {
+        let root = self.workspace.as_ref()?;
+        let mut files = Vec::new();
+        collect_files(root, &mut files);
+        files.sort();
+
+        let mut hasher = DefaultHasher::new();
+        for path in files.iter().filter(|path| !self.excluded.contains(path)) {
+            let Ok(metadata) = path.metadata() else {
+                continue;
+            };
+            path.hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+}
+
+/// Recursively collect regular files under `dir`, skipping build and VCS directories.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            let skipped = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| SKIPPED_DIRS.contains(&name));
+            if !skipped {
+                collect_files(&path, files);
+            }
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+/// Canonicalize a file that may not exist yet by canonicalizing its directory.
+fn canonicalize_file(path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match (parent.canonicalize(), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Normalize output lines so timestamps and counters don't hide a repeat.
+fn normalize(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .map(|line| {
+            let mut normalized = String::new();
+            let mut last = None;
+            for c in line.trim().chars() {
+                let c = if c.is_ascii_digit() {
+                    '#'
+                } else if c.is_whitespace() {
+                    ' '
+                } else {
+                    c.to_ascii_lowercase()
+                };
+                if !(matches!(c, '#' | ' ') && last == Some(c)) {
+                    normalized.push(c);
+                }
+                last = Some(c);
+            }
+            normalized
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Jaccard similarity of two line sets (1.0 when both are empty).
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_workspace(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_stall_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).expect("should create workspace");
+        dir
+    }
+
+    #[test]
+    fn test_normalize_ignores_numbers_case_and_spacing() {
+        let a = normalize("Ran 12 tests in 3.5s\n\n  All  GOOD ");
+        let b = normalize("ran 7 tests in 10.25s\nall good");
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = normalize("one\ntwo\nthree\nfour");
+        let b = normalize("one\ntwo\nthree\nfive");
+        assert!((similarity(&a, &b) - 0.6).abs() < f64::EPSILON);
+        assert_eq!(similarity(&HashSet::new(), &HashSet::new()), 1.0);
+    }
+
+    #[test]
+    fn test_prd_progress_resets_count() {
+        let mut detector = StallDetector::new(None, &[]);
+        assert_eq!(detector.observe(Some(1), Some(1), "same"), 0);
+        assert_eq!(detector.observe(Some(1), Some(1), "same"), 1);
+        assert_eq!(detector.observe(Some(1), Some(1), "same"), 2);
+        assert_eq!(detector.observe(Some(1), Some(2), "same"), 0);
+    }
+
+    #[test]
+    fn test_reopened_story_is_not_progress() {
+        let mut detector = StallDetector::new(None, &[]);
+        detector.observe(Some(1), Some(2), "a");
+        // The agent reopens a story and then marks it passing again
+        assert_eq!(detector.observe(Some(2), Some(1), "a"), 1);
+        assert_eq!(detector.observe(Some(1), Some(2), "a"), 2);
+    }
+
+    #[test]
+    fn test_new_output_counts_without_workspace() {
+        let mut detector = StallDetector::new(None, &[]);
+        assert_eq!(detector.observe(None, None, "reading files"), 0);
+        assert_eq!(detector.observe(None, None, "editing src/main.rs"), 0);
+        assert_eq!(detector.observe(None, None, "editing src/main.rs"), 1);
+    }
+
+    #[test]
+    fn test_workspace_changes() {
+        let dir = temp_workspace("changes");
+        let log = dir.join("progress.txt");
+        let mut detector = StallDetector::new(Some(&dir), &[&log]);
+
+        // No file changes: different output alone is not progress
+        assert_eq!(detector.observe(None, None, "first attempt"), 1);
+
+        std::fs::write(dir.join("lib.rs"), "fn main() {}").unwrap();
+        assert_eq!(detector.observe(None, None, "second attempt"), 0);
+
+        // Files the runner writes and skipped directories are ignored
+        std::fs::write(&log, "entry").unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target").join("out"), "build").unwrap();
+        assert_eq!(detector.observe(None, None, "third attempt"), 1);
+
+        // Changing files while repeating the same output is not progress
+        std::fs::write(dir.join("lib.rs"), "fn main() { loop {} }").unwrap();
+        assert_eq!(detector.observe(None, None, "third attempt"), 2);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
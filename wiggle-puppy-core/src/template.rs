@@ -0,0 +1,365 @@
+//! Prompt templates rendered before each iteration.
+//!
+//! Prompts may reference variables as `{{name}}`. The runner provides these
+//! built-in variables, which render as empty text when they don't apply
+//! (for example, without a PRD):
+//!
+//! - `iteration`, `max_iterations`: the current iteration (1-indexed) and
+//!   the iteration limit
+//! - `next_story.id`, `next_story.title`, `next_story.description`: the next
+//!   story in the PRD
+//! - `next_story.acceptance_criteria`: the next story's criteria, one
+//!   `- ` bullet per line
+//! - `prd.completed`, `prd.total`: completed and total story counts
+//! - `progress_tail`: the last lines of the progress log
+//! - `last_error`: the agent error from the previous iteration
+//!
+//! Custom variables come from `Config::template_var`; built-ins take
+//! precedence over custom variables of the same name. Referencing any other
+//! variable fails with `Error::PromptTemplateError`.
+//!
+//! Prompts can also pull in shared text with `{{> path}}`, resolved relative
+//! to the including file (or the working directory for inline prompts).
+//! Includes are expanded before variables are rendered, so included files
+//! may use variables too.
+//!
+//! A backslash escapes the braces: `\{{name}}` and `\{{> path}}` render as
+//! `{{name}}` and `{{> path}}`, for prompts that show other template syntax
+//! such as `$\{{ secrets.TOKEN }}`. `Config::prompt_templating` turns
+//! templating off altogether.
+
+use crate::error::{Error, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Matches `{{> path}}`, with optional spaces around the path, or an
+/// escaped `\{{` (with no path captured).
+static INCLUDE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\\{\{|\{\{>\s*([^}]+?)\s*\}\}").expect("include regex is valid")
+});
+
+/// Matches `{{name}}`, with optional spaces inside the braces, or an
+/// escaped `\{{` (with no name captured).
+static VARIABLE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\\{\{|\{\{\s*([A-Za-z_][A-Za-z0-9_.]*)\s*\}\}").expect("variable regex is valid")
+});
+
+/// Variables available to a prompt template.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TemplateVars {
+    /// Variable values by name.
+    values: BTreeMap<String, String>,
+}
+
+impl TemplateVars {
+    /// Create an empty set of variables.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a variable, replacing any previous value.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.values.insert(name.into(), value.into());
+        self
+    }
+
+    /// Get a variable's value.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for TemplateVars {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            values: iter
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        }
+    }
+}
+
+/// Read a prompt file, expanding `{{> path}}` includes.
+///
+/// # Errors
+///
+/// Returns `Error::PromptReadError` if the file or an included file can't be
+/// read, or `Error::PromptIncludeCycle` if a file ends up including itself.
+pub fn read_with_includes(path: &Path) -> Result<String> {
+    read_file(path, &mut Vec::new())
+}
+
+/// Expand `{{> path}}` includes in text, resolving relative paths against `base_dir`.
+///
+/// # Errors
+///
+/// Returns the same errors as `read_with_includes`.
+pub fn expand_includes(text: &str, base_dir: &Path) -> Result<String> {
+    expand(text, base_dir, &mut Vec::new())
+}
+
+/// Read a file and expand its includes; `stack` holds the files being read.
+fn read_file(path: &Path, stack: &mut Vec<PathBuf>) -> Result<String> {
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&key) {
+        return Err(Error::PromptIncludeCycle {
+            path: path.to_path_buf(),
+        });
+    }
+    let text = std::fs::read_to_string(path).map_err(|source| Error::PromptReadError {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    stack.push(key);
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let expanded = expand(&text, base_dir, stack);
+    stack.pop();
+    expanded
+}
+
+/// Replace each include in `text` with the included file's expanded content.
+fn expand(text: &str, base_dir: &Path, stack: &mut Vec<PathBuf>) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut last = 0;
+    for captures in INCLUDE_RE.captures_iter(text) {
+        // Escaped braces are kept for `render` to unescape
+        let Some(path) = captures.get(1) else {
+            continue;
+        };
+        let whole = captures.get(0).expect("capture 0 is the whole match");
+        let included = read_file(&base_dir.join(path.as_str()), stack)?;
+        expanded.push_str(&text[last..whole.start()]);
+        // The directive usually sits on its own line, which supplies the newline
+        expanded.push_str(included.strip_suffix('\n').unwrap_or(&included));
+        last = whole.end();
+    }
+    expanded.push_str(&text[last..]);
+    Ok(expanded)
+}
+
+/// Parse a `name=value` variable definition, as given to `--var`.
+///
+/// # Errors
+///
+/// Returns a message if there is no `=` or the name isn't a valid variable
+/// name (letters, digits, `_` and `.`, not starting with a digit or `.`).
+pub fn parse_var(spec: &str) -> std::result::Result<(String, String), String> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("invalid variable '{}' (expected NAME=VALUE)", spec))?;
+    let name = name.trim();
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid {
+        return Err(format!("invalid variable name '{}'", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Replace every `{{name}}` in `template` with its value.
+///
+/// Text in double braces that isn't a variable name is left as is.
+///
+/// # Examples
+///
+/// ```
+/// use wiggle_puppy_core::template::{render, TemplateVars};
+///
+/// let mut vars = TemplateVars::new();
+/// vars.set("iteration", "3").set("next_story.title", "Add login");
+///
+/// let prompt = render("Iteration {{iteration}}: {{ next_story.title }}", &vars).unwrap();
+/// assert_eq!(prompt, "Iteration 3: Add login");
+/// ```
+///
+/// # Errors
+///
+/// Returns `Error::PromptTemplateError` for the first variable that isn't set.
+pub fn render(template: &str, vars: &TemplateVars) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut last = 0;
+    for captures in VARIABLE_RE.captures_iter(template) {
+        let whole = captures.get(0).expect("capture 0 is the whole match");
+        let value = match captures.get(1) {
+            Some(name) => vars
+                .get(name.as_str())
+                .ok_or_else(|| Error::PromptTemplateError {
+                    variable: name.as_str().to_string(),
+                    line: template[..whole.start()].matches('\n').count() + 1,
+                })?,
+            // An escaped `\{{` is literal braces
+            None => "{{",
+        };
+        rendered.push_str(&template[last..whole.start()]);
+        rendered.push_str(value);
+        last = whole.end();
+    }
+    rendered.push_str(&template[last..]);
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_variables() {
+        let vars: TemplateVars = [("prd.completed", "2"), ("prd.total", "5")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            render("Done: {{prd.completed}}/{{ prd.total }}", &vars).unwrap(),
+            "Done: 2/5"
+        );
+    }
+
+    #[test]
+    fn test_render_leaves_other_braces() {
+        let vars = TemplateVars::new();
+        let template = "Use {{> shared.md}} and {{ }} and {single}";
+        assert_eq!(render(template, &vars).unwrap(), template);
+    }
+
+    #[test]
+    fn test_render_unescapes_braces() {
+        let vars: TemplateVars = [("iteration", "1")].into_iter().collect();
+        assert_eq!(
+            render(
+                "token: $\\{{ secrets.X }}, iteration \\{{iteration}} is {{iteration}}",
+                &vars
+            )
+            .unwrap(),
+            "token: ${{ secrets.X }}, iteration {{iteration}} is 1"
+        );
+    }
+
+    #[test]
+    fn test_render_missing_variable() {
+        let vars: TemplateVars = [("iteration", "1")].into_iter().collect();
+        let err = render("Iteration {{iteration}}\n\nFix {{next_story.titel}}", &vars)
+            .expect_err("unknown variable should fail");
+        assert!(matches!(
+            &err,
+            Error::PromptTemplateError { variable, line: 3 } if variable == "next_story.titel"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "prompt template references unknown variable 'next_story.titel' on line 3"
+        );
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("team=web platform"),
+            Ok(("team".to_string(), "web platform".to_string()))
+        );
+        assert_eq!(
+            parse_var("repo.url=https://x.test/?a=b"),
+            Ok(("repo.url".to_string(), "https://x.test/?a=b".to_string()))
+        );
+        assert_eq!(
+            parse_var("empty="),
+            Ok(("empty".to_string(), String::new()))
+        );
+        assert!(parse_var("no-equals").is_err());
+        assert!(parse_var("1st=x").is_err());
+        assert!(parse_var("=x").is_err());
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "wiggle_puppy_test_include_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("shared")).expect("should create dir");
+        dir
+    }
+
+    #[test]
+    fn test_includes_resolve_relative_to_including_file() {
+        let dir = temp_dir("relative");
+        std::fs::write(
+            dir.join("PROMPT.md"),
+            "# Task\n{{> shared/standards.md}}\nFix {{next_story.title}}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("shared").join("standards.md"),
+            "Standards:\n{{>layout.md}}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("shared").join("layout.md"), "- src/ holds code\n").unwrap();
+
+        assert_eq!(
+            read_with_includes(&dir.join("PROMPT.md")).unwrap(),
+            "# Task\nStandards:\n- src/ holds code\nFix {{next_story.title}}\n"
+        );
+        assert_eq!(
+            expand_includes("See {{> layout.md}}.", &dir.join("shared")).unwrap(),
+            "See - src/ holds code."
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = temp_dir("cycle");
+        std::fs::write(dir.join("a.md"), "{{> shared/b.md}}").unwrap();
+        std::fs::write(dir.join("shared").join("b.md"), "{{> ../a.md}}").unwrap();
+
+        let err = read_with_includes(&dir.join("a.md")).expect_err("cycle should fail");
+        assert!(matches!(err, Error::PromptIncludeCycle { .. }));
+
+        // Including the same file twice is not a cycle
+        std::fs::write(dir.join("c.md"), "{{> shared/d.md}} {{> shared/d.md}}").unwrap();
+        std::fs::write(dir.join("shared").join("d.md"), "d").unwrap();
+        assert_eq!(read_with_includes(&dir.join("c.md")).unwrap(), "d d");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_missing_include() {
+        let err = expand_includes("{{> does-not-exist.md}}", Path::new("/nonexistent"))
+            .expect_err("missing include should fail");
+        assert!(matches!(
+            err,
+            Error::PromptReadError { path, .. } if path == Path::new("/nonexistent/does-not-exist.md")
+        ));
+    }
+
+    #[test]
+    fn test_escaped_include_is_left_for_render() {
+        let expanded = expand_includes("See \\{{> partial}}", Path::new("/nonexistent"))
+            .expect("escaped include should not be read");
+        assert_eq!(expanded, "See \\{{> partial}}");
+        assert_eq!(
+            render(&expanded, &TemplateVars::new()).unwrap(),
+            "See {{> partial}}"
+        );
+    }
+
+    #[test]
+    fn test_values_are_not_rendered_again() {
+        let vars: TemplateVars = [("last_error", "expected {{iteration}}")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            render("{{last_error}}", &vars).unwrap(),
+            "expected {{iteration}}"
+        );
+    }
+}
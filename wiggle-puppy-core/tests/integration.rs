@@ -6,7 +6,9 @@
 
 use std::fs;
 use std::time::Duration;
-use wiggle_puppy_core::{Config, Outcome, Runner};
+use wiggle_puppy_core::{
+    Checkpoint, Config, ErrorClass, ErrorPattern, Event, EventEnvelope, Outcome, Runner,
+};
 
 /// Creates a mock agent script that tracks call count via a file and
 /// outputs the completion phrase on the specified call number.
@@ -122,7 +124,7 @@ async fn test_runner_completes_on_third_iteration() {
     let mut iterations_started = vec![];
     let mut completed = false;
 
-    while let Some(event) = events.recv().await {
+    while let Some(EventEnvelope { event, .. }) = events.recv().await {
         match event {
             wiggle_puppy_core::Event::Started { .. } => started = true,
             wiggle_puppy_core::Event::IterationStarted { iteration, .. } => {
@@ -274,7 +276,7 @@ async fn test_mock_agent_echoes_input() {
     // Collect agent output events
     drop(runner);
     let mut output_lines = vec![];
-    while let Some(event) = events.recv().await {
+    while let Some(EventEnvelope { event, .. }) = events.recv().await {
         if let wiggle_puppy_core::Event::AgentOutput { text, .. } = event {
             output_lines.push(text);
         }
@@ -291,3 +293,405 @@ async fn test_mock_agent_echoes_input() {
     // Cleanup
     fs::remove_dir_all(&temp_dir).ok();
 }
+
+#[tokio::test]
+async fn test_runner_waits_out_usage_limit_without_counting_failure() {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "wiggle_puppy_test_usage_limit_{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&temp_dir).expect("failed to create temp dir");
+
+    let script_path = temp_dir.join("mock_agent_usage_limit.sh");
+    let counter_path = temp_dir.join("call_count.txt");
+
+    // First call hits a usage limit that has already reset; second call completes
+    let script_content = format!(
+        r#"#!/bin/bash
+COUNTER_FILE="{}"
+COUNT=$(cat "$COUNTER_FILE" 2>/dev/null || echo 0)
+COUNT=$((COUNT + 1))
+echo "$COUNT" > "$COUNTER_FILE"
+
+if [ "$COUNT" -eq 1 ]; then
+    echo "Claude AI usage limit reached|1000000000"
+    sleep 30
+fi
+echo "<promise>COMPLETE</promise>"
+"#,
+        counter_path.display()
+    );
+    fs::write(&script_path, script_content).expect("failed to write script");
+
+    // With no retries and a circuit breaker of 1, any counted failure would stop the run.
+    // The reset time is in the past, so the runner falls back to the configured wait.
+    let config = Config::new()
+        .usage_limit_wait_secs(0)
+        .agent_command("bash")
+        .agent_args(vec![script_path.to_str().unwrap().to_string()])
+        .prompt_text("Test prompt")
+        .completion_phrase("<promise>COMPLETE</promise>")
+        .max_iterations(3)
+        .max_retries(0)
+        .circuit_breaker_threshold(1)
+        .delay(Duration::ZERO)
+        .auto_completion_instruction(false);
+
+    let (runner, mut events, _handle) = Runner::new(config);
+    let outcome = runner.run().await.expect("runner should succeed");
+
+    assert!(
+        outcome.is_completed(),
+        "expected Outcome::Completed, got {:?}",
+        outcome
+    );
+    assert_eq!(outcome.iterations(), 1);
+
+    drop(runner);
+    let mut rate_limited = 0;
+    while let Some(EventEnvelope { event, .. }) = events.recv().await {
+        if let wiggle_puppy_core::Event::RateLimited { .. } = event {
+            rate_limited += 1;
+        }
+    }
+    assert_eq!(rate_limited, 1, "should have emitted one RateLimited event");
+
+    fs::remove_dir_all(&temp_dir).ok();
+}
+
+/// Returns true if the process is gone or only left as a zombie.
+#[cfg(target_os = "linux")]
+fn process_is_dead(pid: u32) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // The state field follows the parenthesised command name
+        Ok(stat) => stat
+            .rsplit(')')
+            .next()
+            .is_some_and(|rest| rest.trim_start().starts_with('Z')),
+        Err(_) => true,
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_runner_timeout_kills_agent_grandchildren() {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "wiggle_puppy_test_process_tree_{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&temp_dir).expect("failed to create temp dir");
+
+    let script_path = temp_dir.join("mock_agent_forks.sh");
+    let pid_path = temp_dir.join("grandchild.pid");
+
+    // The agent forks a long-lived child, records its pid, then hangs
+    let script_content = format!(
+        r#"#!/bin/bash
+sleep 300 &
+echo $! > "{}"
+wait
+"#,
+        pid_path.display()
+    );
+    fs::write(&script_path, script_content).expect("failed to write script");
+
+    let config = Config::new()
+        .agent_command("bash")
+        .agent_args(vec![script_path.to_str().unwrap().to_string()])
+        .prompt_text("Test prompt")
+        .agent_timeout_secs(1)
+        .max_iterations(1)
+        .max_retries(0)
+        .delay(Duration::ZERO)
+        .auto_completion_instruction(false);
+
+    let (runner, _events, _handle) = Runner::new(config);
+    let outcome = runner.run().await.expect("runner should succeed");
+    assert!(
+        outcome.is_stopped(),
+        "expected Outcome::Stopped, got {:?}",
+        outcome
+    );
+
+    let pid: u32 = fs::read_to_string(&pid_path)
+        .expect("agent should have written the grandchild pid")
+        .trim()
+        .parse()
+        .expect("failed to parse pid");
+
+    // Give the kernel a moment to deliver the signal
+    let mut dead = false;
+    for _ in 0..50 {
+        if process_is_dead(pid) {
+            dead = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(
+        dead,
+        "grandchild {} should have been killed with the agent",
+        pid
+    );
+
+    fs::remove_dir_all(&temp_dir).ok();
+}
+
+#[tokio::test]
+async fn test_runner_emits_story_changes_from_prd_diff() {
+    let temp_dir =
+        std::env::temp_dir().join(format!("wiggle_puppy_test_prd_diff_{}", std::process::id()));
+    fs::create_dir_all(&temp_dir).expect("failed to create temp dir");
+
+    let script_path = temp_dir.join("mock_agent_edits_prd.sh");
+    let prd_path = temp_dir.join("prd.json");
+
+    let story = |id: &str, title: &str, passes: bool| {
+        format!(
+            r#"{{"id": "{id}", "title": "{title}", "description": "", "priority": 1, "passes": {passes}, "acceptance_criteria": [], "depends_on": []}}"#
+        )
+    };
+    let prd = |stories: Vec<String>| {
+        format!(
+            r#"{{"name": "Test", "branchName": "test", "description": "", "stories": [{}]}}"#,
+            stories.join(", ")
+        )
+    };
+
+    fs::write(
+        &prd_path,
+        prd(vec![
+            story("1", "First", false),
+            story("2", "Second", true),
+            story("3", "Third", false),
+        ]),
+    )
+    .expect("failed to write PRD");
+
+    // The agent completes story 1, reopens story 2, drops story 3 and adds story 4
+    let after = prd(vec![
+        story("1", "First", true),
+        story("2", "Second", false),
+        story("4", "Fourth", false),
+    ]);
+    let script_content = format!(
+        "#!/bin/bash\ncat > \"{}\" <<'PRD'\n{}\nPRD\n",
+        prd_path.display(),
+        after
+    );
+    fs::write(&script_path, script_content).expect("failed to write script");
+
+    let config = Config::new()
+        .agent_command("bash")
+        .agent_args(vec![script_path.to_str().unwrap().to_string()])
+        .prompt_text("Test prompt")
+        .prd_path(&prd_path)
+        .max_iterations(1)
+        .delay(Duration::ZERO)
+        .auto_completion_instruction(false);
+
+    let (runner, mut events, _handle) = Runner::new(config);
+    let outcome = runner.run().await.expect("runner should succeed");
+    assert!(outcome.is_stopped());
+
+    drop(runner);
+    let mut changes = vec![];
+    while let Some(EventEnvelope { event, .. }) = events.recv().await {
+        match event {
+            Event::StoryCompleted { story_id, .. } => changes.push(format!("completed {story_id}")),
+            Event::StoryReopened { story_id, .. } => changes.push(format!("reopened {story_id}")),
+            Event::StoryAdded { story_id, .. } => changes.push(format!("added {story_id}")),
+            Event::StoryRemoved { story_id, .. } => changes.push(format!("removed {story_id}")),
+            _ => {}
+        }
+    }
+    assert_eq!(
+        changes,
+        vec!["completed 1", "reopened 2", "added 4", "removed 3"]
+    );
+
+    fs::remove_dir_all(&temp_dir).ok();
+}
+
+#[tokio::test]
+async fn test_runner_resumes_from_checkpoint() {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "wiggle_puppy_test_checkpoint_{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&temp_dir).expect("failed to create temp dir");
+
+    let script_path = temp_dir.join("mock_agent_never_complete.sh");
+    let counter_path = temp_dir.join("call_count.txt");
+    let checkpoint_path = temp_dir.join("checkpoint.json");
+
+    let script_content = create_mock_agent_script(counter_path.to_str().unwrap(), 999);
+    fs::write(&script_path, script_content).expect("failed to write script");
+
+    let config = Config::new()
+        .agent_command("bash")
+        .agent_args(vec![script_path.to_str().unwrap().to_string()])
+        .prompt_text("Test prompt")
+        .checkpoint_path(&checkpoint_path)
+        .max_iterations(2)
+        .delay(Duration::ZERO)
+        .auto_completion_instruction(false);
+
+    // First run: two iterations, then "dies" at its budget
+    let (runner, _events, _handle) = Runner::new(config.clone());
+    let outcome = runner.run().await.expect("runner should succeed");
+    assert_eq!(outcome.iterations(), 2);
+
+    let checkpoint = Checkpoint::load(&checkpoint_path).expect("checkpoint should be written");
+    assert_eq!(checkpoint.iteration, 2);
+    assert_eq!(checkpoint.run_id, runner.run_id());
+
+    // Resumed run continues the iteration count against the same budget
+    let resumed = config.clone().max_iterations(4).resume(true);
+    let (runner, mut events, _handle) = Runner::new(resumed);
+    let outcome = runner.run().await.expect("runner should succeed");
+    assert_eq!(outcome.iterations(), 4);
+    assert_eq!(runner.run_id(), checkpoint.run_id);
+
+    drop(runner);
+    let mut iterations_started = vec![];
+    let mut resumed_from = None;
+    while let Some(EventEnvelope { event, run_id, .. }) = events.recv().await {
+        // Every event of the resumed run carries the checkpoint's id
+        assert_eq!(run_id, checkpoint.run_id);
+        match event {
+            Event::RunResumed { run_id, iteration } => {
+                assert_eq!(run_id, checkpoint.run_id);
+                resumed_from = Some(iteration);
+            }
+            Event::IterationStarted { iteration, .. } => iterations_started.push(iteration),
+            _ => {}
+        }
+    }
+    assert_eq!(resumed_from, Some(2));
+    assert_eq!(iterations_started, vec![3, 4]);
+
+    let calls: u32 = fs::read_to_string(&counter_path)
+        .expect("failed to read counter")
+        .trim()
+        .parse()
+        .expect("failed to parse counter");
+    assert_eq!(calls, 4);
+
+    // A different prompt is refused unless forced
+    let mismatched = config.clone().prompt_text("Other prompt").resume(true);
+    let (runner, _events, _handle) = Runner::new(mismatched.clone());
+    assert!(matches!(
+        runner.run().await,
+        Err(wiggle_puppy_core::Error::CheckpointMismatch { .. })
+    ));
+
+    let forced = mismatched.max_iterations(5).force_resume(true);
+    let (runner, _events, _handle) = Runner::new(forced);
+    let outcome = runner.run().await.expect("forced resume should succeed");
+    assert_eq!(outcome.iterations(), 5);
+
+    fs::remove_dir_all(&temp_dir).ok();
+}
+
+#[tokio::test]
+async fn test_cancelled_run_saves_checkpoint() {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "wiggle_puppy_test_checkpoint_cancel_{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&temp_dir).expect("failed to create temp dir");
+    let checkpoint_path = temp_dir.join("checkpoint.json");
+
+    let config = Config::new()
+        .agent_command("echo")
+        .agent_args(vec![])
+        .prompt_text("connection reset")
+        .error_patterns(vec![ErrorPattern::literal(
+            "connection reset",
+            ErrorClass::Transient,
+        )])
+        .initial_backoff_secs(60)
+        .checkpoint_path(&checkpoint_path)
+        .max_iterations(5)
+        .delay(Duration::ZERO)
+        .auto_completion_instruction(false);
+    let (runner, mut events, handle) = Runner::new(config);
+
+    // Cancel during the backoff after the first failed attempt
+    tokio::spawn(async move {
+        while let Some(EventEnvelope { event, .. }) = events.recv().await {
+            if matches!(event, Event::RetryScheduled { .. }) {
+                handle.cancel();
+            }
+        }
+    });
+
+    let outcome = tokio::time::timeout(Duration::from_secs(5), runner.run())
+        .await
+        .expect("cancel should interrupt the backoff")
+        .expect("runner should succeed");
+    assert_eq!(outcome.iterations(), 1);
+
+    // The failure that happened before the cancel survives into a resume
+    let checkpoint = Checkpoint::load(&checkpoint_path).expect("checkpoint should be written");
+    assert_eq!(checkpoint.iteration, 1);
+    assert_eq!(checkpoint.consecutive_failures, 1);
+
+    fs::remove_dir_all(&temp_dir).ok();
+}
+
+#[tokio::test]
+async fn test_resume_of_finished_run_does_not_rerun_agent() {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "wiggle_puppy_test_checkpoint_finished_{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&temp_dir).expect("failed to create temp dir");
+
+    let script_path = temp_dir.join("mock_agent_complete.sh");
+    let counter_path = temp_dir.join("call_count.txt");
+    let checkpoint_path = temp_dir.join("checkpoint.json");
+
+    let script_content = create_mock_agent_script(counter_path.to_str().unwrap(), 2);
+    fs::write(&script_path, script_content).expect("failed to write script");
+
+    let config = Config::new()
+        .agent_command("bash")
+        .agent_args(vec![script_path.to_str().unwrap().to_string()])
+        .prompt_text("Test prompt")
+        .checkpoint_path(&checkpoint_path)
+        .max_iterations(10)
+        .delay(Duration::ZERO)
+        .auto_completion_instruction(false);
+
+    let (runner, _events, _handle) = Runner::new(config.clone());
+    let outcome = runner.run().await.expect("runner should succeed");
+    assert!(outcome.is_completed());
+    assert_eq!(outcome.iterations(), 2);
+
+    let checkpoint = Checkpoint::load(&checkpoint_path).expect("checkpoint should be written");
+    assert_eq!(checkpoint.finished, Some(outcome.clone()));
+
+    // Resuming reports the recorded outcome without running the agent again
+    let (runner, mut events, _handle) = Runner::new(config.resume(true));
+    let resumed = runner.run().await.expect("runner should succeed");
+    assert_eq!(resumed, outcome);
+
+    drop(runner);
+    while let Some(EventEnvelope { event, .. }) = events.recv().await {
+        assert!(!matches!(
+            event,
+            Event::IterationStarted { .. } | Event::RunResumed { .. }
+        ));
+    }
+
+    let calls: u32 = fs::read_to_string(&counter_path)
+        .expect("failed to read counter")
+        .trim()
+        .parse()
+        .expect("failed to parse counter");
+    assert_eq!(calls, 2);
+
+    fs::remove_dir_all(&temp_dir).ok();
+}